@@ -28,8 +28,8 @@ pub enum Channel {
     VBat,
     PowerConsumption,
     Out1,
-    _VRef,
-    _Press,
+    VRef,
+    Press,
     Volt5,
     Supply,
     Volt3,
@@ -41,8 +41,8 @@ impl Into<u8> for Channel {
             Channel::VBat => 0,
             Channel::PowerConsumption => 1,
             Channel::Out1 => 2,
-            Channel::_VRef => 3,
-            Channel::_Press => 4,
+            Channel::VRef => 3,
+            Channel::Press => 4,
             Channel::Volt5 => 5,
             Channel::Supply => 6,
             Channel::Volt3 => 7,
@@ -50,18 +50,41 @@ impl Into<u8> for Channel {
     }
 }
 
+impl Channel {
+    pub const VALUES: [Channel; 8] = [
+        Channel::VBat,
+        Channel::PowerConsumption,
+        Channel::Out1,
+        Channel::VRef,
+        Channel::Press,
+        Channel::Volt5,
+        Channel::Supply,
+        Channel::Volt3,
+    ];
+
+    /// Channel wired to input `index` of the MCP3208
+    pub fn from_index(index: u8) -> Option<Channel> {
+        Self::VALUES
+            .into_iter()
+            .find(|&channel| Into::<u8>::into(channel) == index)
+    }
+}
+
 pub fn read_adc(channel: Channel) -> Result<u16, ()> {
     // outputs the raw adc values of all channels
     /*if let Ok(mut mcp3208) = Mcp3208::new("/dev/spidev0.0") {
         Channel::VALUES.iter().for_each(|&channel| {
-            println!(
-                "channel #{}: {}",
-                channel as u8,
-                mcp3208.read_adc_single(channel).unwrap()
+            logging::debug(
+                "adc",
+                "Lettura grezza",
+                &[
+                    ("canale", &(channel as u8)),
+                    ("valore", &mcp3208.read_adc_single(channel).unwrap()),
+                ],
             );
         });
     } else {
-        println!("Could not do stuff");
+        logging::error("adc", "Impossibile aprire l'ADC", &[]);
     }*/
 
     fn create_write_buffer(channel: u8) -> [u8; 4] {
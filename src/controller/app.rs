@@ -2,29 +2,46 @@ use iced;
 use iced::widget::text_input;
 use iced::{Application, Command, Element};
 use iced_native::widget::scrollable::{Id, RelativeOffset};
+use std::collections::BTreeMap;
 use std::fs;
 use std::time::{Duration, Instant};
 use tokio::sync::mpsc;
 
-use super::{flashing, worker};
+use super::{audit, flashing, frequency, limits, operators, selftest, worker};
 use super::{reles, save_report};
 use crate::controller::adc;
+use crate::logging;
 use crate::model::{
-    Barcode, Configuration, DigiblockState, Model, Report, RgbLight, StepState, TestState,
-    TestStep, TestStepResult,
+    Attachment, AuditAction, AuditEntry, Barcode, Calibration, ColorReading, ColorSensorKind,
+    Configuration, DigiblockInfo, DigiblockState, FirmwareImage, FixtureCheck, FlasherKind,
+    FlashingError, FlashingPhase, FlashingProgress, LcdPattern, LcdSegment, Model,
+    OperatorRegistry, Permission, Report, RgbLight, StepLimits, StepState, TestState, TestStep,
+    TestStepResult,
 };
 use crate::view;
 
 const PORT: &str = "/dev/ttyACM0";
 const CONFIG: &str = "./config.yaml";
+const FLASHING_TIMES: &str = "./flashing_times.yaml";
 const BASE_VARIANT: &str = "1";
 
 #[derive(Clone, Debug)]
 pub enum ControllerMessage {
     Connect(String),
-    SetLight(RgbLight),
+    SetLight(RgbLight, u8),
+    SetLcdPattern(LcdPattern),
+    PressButton(reles::Rele),
+    /// Camera check of the LCD, from the given V4L2 device or image directory
+    LcdCheck(String),
+    /// Colour sensor check of a backlight colour at the given intensities, with the minimum
+    /// clear count at full intensity
+    ColorCheck(RgbLight, ColorSensorKind, Vec<u8>, u16),
     Disconnect,
     Test(TestStep),
+    Flash(TestStep, FirmwareImage, FlasherKind, Option<String>),
+    // Named in full, `OptionBytes` is also a test step
+    OptionBytes(crate::model::OptionBytes, FlasherKind, Option<String>),
+    WriteManufacturingData(Barcode),
 }
 
 #[derive(Clone, Debug)]
@@ -32,7 +49,15 @@ pub enum ControllerEvent {
     Ready(mpsc::Sender<ControllerMessage>),
     Log(String),
     Update(DigiblockState),
+    DeviceInfo(DigiblockInfo),
+    FlashingOutput(TestStep, Vec<String>, Option<FlashingError>),
+    FlashingProgress(TestStep, FlashingPhase, f32),
+    /// Segments checked by the camera and pictures taken
+    LcdResult(Vec<LcdSegment>, Vec<Attachment>),
+    ColorResult(Vec<ColorReading>),
     TestResult(TestStep, Option<f64>, bool),
+    /// The fixture failed while testing the step, which says nothing about the board
+    FixtureFault(TestStep, String),
 }
 
 #[derive(Clone, Debug)]
@@ -41,6 +66,10 @@ pub enum Event {
     UpdateVBat(Instant),
     ViewEvent(view::Event),
     ControllerEvent(ControllerEvent),
+    SelfTestDone(Vec<FixtureCheck>),
+    CalibrationDone(Result<f64, String>),
+    /// Window closed or SIGTERM received, the relays must be opened before exiting
+    Shutdown,
 }
 
 pub struct App {
@@ -56,54 +85,131 @@ impl Application for App {
     type Flags = ();
 
     fn new((): Self::Flags) -> (App, Command<Event>) {
-        let config = fs::read(CONFIG)
+        let mut config = fs::read(CONFIG)
             .map(|v| {
                 serde_yaml::from_str(String::from_utf8(v).unwrap_or("".into()).as_str())
                     .unwrap_or(Configuration::default())
             })
             .unwrap_or(Configuration::default());
+        logging::configure(config.livello_log);
+        if config.livelli_retroilluminazione.is_empty() {
+            logging::error(
+                "app",
+                "livelli_retroilluminazione vuoto, uso i livelli predefiniti",
+                &[],
+            );
+            config.livelli_retroilluminazione = Configuration::default().livelli_retroilluminazione;
+        }
+
+        let flashing_times = fs::read_to_string(FLASHING_TIMES)
+            .ok()
+            .and_then(|v| serde_yaml::from_str(v.as_str()).ok())
+            .unwrap_or_default();
+
+        let (firmware, firmware_error) = match flashing::load_manifest() {
+            Ok(manifest) => (Some(manifest), None),
+            Err(e) => (None, Some(e)),
+        };
+
+        let (operators, operators_error, operator) = match (operators::load(), config.operatore) {
+            (Ok(Some(registry)), _) => (registry, None, None),
+            (Ok(None), Some(code)) => {
+                logging::warning(
+                    "app",
+                    "Registro operatori assente, accesso dell'operatore configurato",
+                    &[("operatore", &code)],
+                );
+                let registry = operators::fallback(code);
+                let operator = registry.operatori.first().cloned();
+                (registry, None, operator)
+            }
+            (Ok(None), None) => (
+                OperatorRegistry::default(),
+                Some(String::from("Registro operatori non trovato")),
+                None,
+            ),
+            (Err(e), _) => (OperatorRegistry::default(), Some(e), None),
+        };
 
-        (
-            App {
-                model: Model {
-                    config,
-                    ..Model::default()
-                },
-                sender: None,
-                start_ts: Instant::now(),
+        reles::configure(config.attuatori_tasti);
+        let calibration = frequency::configure(config.generatore_frequenza);
+        limits::configure();
+        worker::configure_current_sense(config.sensore_corrente);
+
+        let mut app = App {
+            model: Model {
+                config,
+                firmware,
+                firmware_error,
+                flashing_times,
+                vref: worker::read_vref().ok(),
+                maintenance: reles::counters(),
+                calibration,
+                operators,
+                operators_error,
+                operator,
+                ..Model::default()
             },
-            Command::none(),
-        )
+            sender: None,
+            start_ts: Instant::now(),
+        };
+        if let Some(operator) = app.model.operator.clone() {
+            let detail = format!("{} (senza registro)", operator.nome);
+            app.audit(AuditAction::Login, detail);
+        }
+
+        let command = app.start_self_test();
+        (app, command)
     }
 
     fn title(&self) -> String {
         String::from("Digiblock Test")
     }
 
-    fn view(&self) -> Element<Event> {
+    fn view(&self) -> Element<'_, Event> {
         view::view(&self.model).map(Event::ViewEvent)
     }
 
     fn update(&mut self, event: Event) -> Command<Event> {
+        // Records from other modules and threads, such as the relays
+        self.model.sync_log();
+
         match event {
             Event::ControllerEvent(ControllerEvent::Ready(sender)) => {
                 self.sender = Some(sender);
                 text_input::focus(text_input::Id::new("0"))
             }
             Event::ControllerEvent(ControllerEvent::Log(msg)) => {
-                self.model.logs.push(msg);
+                logging::info("worker", msg, &[]);
+                self.model.sync_log();
                 iced::widget::scrollable::snap_to(Id::new("logs"), RelativeOffset::END)
             }
             Event::UpdateLight(_) => {
-                let light = self.model.next_light();
-                self.controller_message(ControllerMessage::SetLight(light));
+                if let TestState::Testing(step, _) = self.model.state {
+                    if let Some(light) = step.light() {
+                        let intensity = self.model.next_light_level();
+                        self.controller_message(ControllerMessage::SetLight(light, intensity));
+                    }
+                }
                 Command::none()
             }
             Event::UpdateVBat(_) => {
                 let vbat = worker::read_vbat().ok();
                 self.model.add_vbat(vbat);
-                if vbat.is_some() {
-                    //println!("VBat {:?}", vbat.unwrap());
+                if let TestState::Ready = self.model.state {
+                    self.model.vref = worker::read_vref().ok();
+                }
+                if let TestState::Testing(
+                    step @ (TestStep::UiLeftButton | TestStep::UiRightButton),
+                    StepState::Waiting,
+                ) = self.model.state
+                {
+                    let timeout = Duration::from_secs(self.model.config.timeout_tasti);
+                    if self.start_ts.elapsed() > timeout {
+                        self.model.log(format!("Tempo scaduto: {}", step.metadata().1));
+                        self.add_test(step, false, None);
+                        self.model.state = TestState::Testing(step, StepState::Failed);
+                    }
                 }
                 Command::none()
             }
@@ -111,12 +217,7 @@ impl Application for App {
                 self.model.digiblock_update(state);
 
                 match self.model.state {
-                    TestState::Testing(TestStep::Connecting, _) => {
-                        self.model.state =
-                            TestState::Testing(TestStep::UiLeftButton, StepState::Waiting);
-                        self.model.light = RgbLight::default();
-                    }
-                    TestState::Testing(TestStep::UiLeftButton, _) => {
+                    TestState::Testing(TestStep::UiLeftButton, StepState::Waiting) => {
                         if self.model.digiblock_state.left_button
                             && self.model.digiblock_state.right_button
                         {
@@ -124,24 +225,18 @@ impl Application for App {
                             self.model.log("Tasto destro rilevato");
                             self.add_test(TestStep::UiLeftButton, true, None);
                             self.add_test(TestStep::UiRightButton, true, None);
-                            self.start_ts = Instant::now();
-                            self.model.state =
-                                TestState::Testing(TestStep::UiLCD, StepState::Waiting);
+                            return self.start_lcd_test();
                         } else if self.model.digiblock_state.left_button {
                             self.model.log("Tasto sinistro rilevato");
                             self.add_test(TestStep::UiLeftButton, true, None);
-                            self.start_ts = Instant::now();
-                            self.model.state =
-                                TestState::Testing(TestStep::UiRightButton, StepState::Waiting);
+                            return self.start_button_test(TestStep::UiRightButton);
                         }
                     }
-                    TestState::Testing(TestStep::UiRightButton, _) => {
+                    TestState::Testing(TestStep::UiRightButton, StepState::Waiting) => {
                         if self.model.digiblock_state.right_button {
                             self.model.log("Tasto destro rilevato");
                             self.add_test(TestStep::UiRightButton, true, None);
-                            self.start_ts = Instant::now();
-                            self.model.state =
-                                TestState::Testing(TestStep::UiLCD, StepState::Waiting);
+                            return self.start_lcd_test();
                         }
                     }
                     _ => (),
@@ -149,8 +244,112 @@ impl Application for App {
 
                 Command::none()
             }
+            Event::ControllerEvent(ControllerEvent::DeviceInfo(info)) => {
+                self.model
+                    .log(format!("Firmware di collaudo: {}", info.firmware_version));
+                self.model.log(format!("UID MCU: {}", info.uid));
+                self.model
+                    .log(format!("Revisione hardware: {}", info.hardware_revision));
+                self.model.report.device = Some(info);
+                Command::none()
+            }
+            Event::ControllerEvent(ControllerEvent::FlashingOutput(step, output, error)) => {
+                if let Some(error) = error {
+                    let (code, description) = error.metadata();
+                    self.model.log(format!("{} ({})", description, code));
+                    self.model.report.flashing_errors.insert(step, error);
+                }
+
+                for line in &output {
+                    logging::debug(
+                        "flashing",
+                        line,
+                        &[
+                            ("programmatore", &self.model.config.programmatore.name()),
+                            ("prova", &step.metadata().0),
+                        ],
+                    );
+                }
+                self.model.sync_log();
+
+                self.model.report.attachments.push(Attachment {
+                    name: format!(
+                        "{}-{}.log",
+                        self.model.config.programmatore.name(),
+                        step.metadata().0
+                    ),
+                    content: output.join("\n").into_bytes(),
+                });
+                Command::none()
+            }
+            Event::ControllerEvent(ControllerEvent::FlashingProgress(step, phase, progress)) => {
+                // Late events from a programming already over must not move the next one
+                if !matches!(self.model.state, TestState::Testing(current, _) if current == step) {
+                    return Command::none();
+                }
+                if let Some(flashing) = &mut self.model.flashing {
+                    // Programmers may report a phase again after it is over
+                    if phase >= flashing.phase {
+                        flashing.phase = phase;
+                        flashing.progress = progress;
+                    }
+                }
+                Command::none()
+            }
+            Event::ControllerEvent(ControllerEvent::LcdResult(segments, images)) => {
+                for segment in segments.iter().filter(|s| !s.passed) {
+                    self.model
+                        .log(format!("Segmento LCD {} difettoso", segment.name));
+                }
+                self.model.report.lcd_segments = segments;
+                self.model
+                    .report
+                    .attachments
+                    .retain(|a| !a.name.starts_with("lcd-"));
+                self.model.report.attachments.extend(images);
+                Command::none()
+            }
+            Event::ControllerEvent(ControllerEvent::ColorResult(readings)) => {
+                for reading in &readings {
+                    let [r, g, b] = reading.chromaticity();
+                    self.model.log(format!(
+                        "Retroilluminazione {} al {}%: r {:.2} g {:.2} b {:.2}, {} conteggi, {:.0} lux{}",
+                        reading.light.name(),
+                        reading.intensity,
+                        r,
+                        g,
+                        b,
+                        reading.clear,
+                        reading.lux,
+                        if reading.passed {
+                            ""
+                        } else {
+                            " (fuori dai limiti)"
+                        }
+                    ));
+                }
+                self.model.report.color_readings.extend(readings);
+                Command::none()
+            }
+            Event::ControllerEvent(ControllerEvent::FixtureFault(step, fault)) => {
+                self.model.log(format!("Guasto attrezzatura: {}", fault));
+                let detail = format!("{}: guasto attrezzatura", step.metadata().1);
+                self.audit(AuditAction::Abort, detail);
+                self.conclude_run();
+
+                self.model.fixture_checks = vec![FixtureCheck::new(step.metadata().1, Err(fault))];
+                self.model.state = TestState::FixtureFault;
+                Command::none()
+            }
             Event::ControllerEvent(ControllerEvent::TestResult(step, value, success)) => {
                 use TestStep::*;
+
+                let success = if step == Connecting && success {
+                    self.check_hardware_revision()
+                } else {
+                    success
+                };
+
                 match step {
                     InvertPower => {
                         self.model.log(if success {
@@ -173,6 +372,28 @@ impl Application for App {
                             "Connessione fallita"
                         });
                     }
+                    UiRgbWhite | UiRgbRed | UiRgbGreen | UiRgbBlue => {
+                        self.model.log(format!(
+                            "{}: {}",
+                            step.metadata().1,
+                            if success { "riuscita" } else { "fallita" }
+                        ));
+                    }
+                    UiLCD => {
+                        self.model.log(if success {
+                            "Segmenti LCD verificati"
+                        } else {
+                            "Verifica dei segmenti LCD fallita"
+                        });
+                    }
+                    CurrentIdle | CurrentWhite | CurrentRed | CurrentGreen | CurrentBlue
+                    | CurrentOutput => {
+                        self.model.log(format!(
+                            "{}: {} mA",
+                            step.metadata().1,
+                            value.map(|x| x.to_string()).unwrap_or("---".into())
+                        ));
+                    }
                     AnalogShortCircuit => {
                         self.model.log(if success {
                             "Corto circuito analogico rilevato"
@@ -192,6 +413,12 @@ impl Application for App {
                             value.map(|x| x.to_string()).unwrap_or("---".into())
                         ));
                     }
+                    Pulses => {
+                        self.model.log(format!(
+                            "Impulsi: 1000 - {}",
+                            value.map(|x| x.to_string()).unwrap_or("---".into())
+                        ));
+                    }
                     OutputShortCircuit => {
                         self.model.log(if success {
                             "Corto circuito digitale rilevato"
@@ -212,15 +439,63 @@ impl Application for App {
                         } else {
                             "Caricamento firmware di produzione fallito"
                         });
+                        let version = self
+                            .model
+                            .production_firmware()
+                            .map(|image| image.versione.clone())
+                            .unwrap_or_default();
+                        self.audit(
+                            AuditAction::FirmwareUpdate,
+                            format!(
+                                "firmware {} {}",
+                                version,
+                                if success { "caricato" } else { "non caricato" }
+                            ),
+                        );
+                    }
+                    OptionBytes => {
+                        let options = value
+                            .map(|v| format!("{:#010x}", v as u32))
+                            .unwrap_or("---".into());
+                        self.model.log(format!("Option byte: {}", options));
+                        self.audit(
+                            AuditAction::FirmwareUpdate,
+                            format!("option byte {}", options),
+                        );
+                    }
+                    WriteManufacturingData => {
+                        self.model.log(if success {
+                            "Dati di produzione scritti e verificati"
+                        } else {
+                            "Scrittura dei dati di produzione fallita"
+                        });
+                    }
+                    VerifyProduction => {
+                        self.model.log(if success {
+                            "Firmware di produzione avviato correttamente"
+                        } else {
+                            "Il firmware di produzione non risponde o ha una versione errata"
+                        });
                     }
 
                     _ => (),
                 }
 
-                self.add_test(step, success, value);
+                if success && (step == FlashingTest || step == FlashingProduction) {
+                    self.update_flashing_time(step);
+                }
 
                 let scroll_cmd =
                     iced::widget::scrollable::snap_to(Id::new("logs"), RelativeOffset::END);
+                if step.light().is_some() {
+                    return iced::Command::batch(vec![
+                        self.backlight_result(step, success),
+                        scroll_cmd,
+                    ]);
+                }
+
+                self.add_test(step, success, value);
+
                 if success {
                     iced::Command::batch(vec![self.next_step(step), scroll_cmd])
                 } else {
@@ -229,12 +504,53 @@ impl Application for App {
                 }
             }
 
-            Event::ViewEvent(view::Event::UpdateOperator(val)) => {
-                if val > 0 && val < 100 {
-                    self.model.config.operatore = val;
-                    fs::write(CONFIG, serde_yaml::to_string(&self.model.config).unwrap()).unwrap();
+            Event::ViewEvent(view::Event::BadgeRead(badge)) => {
+                self.model.login_badge = badge;
+                Command::none()
+            }
+            Event::ViewEvent(view::Event::OperatorCodeRead(code)) => {
+                self.model.login_code = code;
+                Command::none()
+            }
+            Event::ViewEvent(view::Event::PinRead(pin)) => {
+                self.model.login_pin = pin;
+                Command::none()
+            }
+            Event::ViewEvent(view::Event::Login) => {
+                let badge = std::mem::take(&mut self.model.login_badge);
+                let code = std::mem::take(&mut self.model.login_code);
+                let pin = std::mem::take(&mut self.model.login_pin);
+                match operators::authenticate(&self.model.operators, &badge, &code, &pin) {
+                    Some(operator) => {
+                        self.model.log(format!(
+                            "Accesso di {} ({})",
+                            operator.nome,
+                            operator.ruolo.name()
+                        ));
+                        let detail = format!("{} ({})", operator.nome, operator.ruolo.name());
+                        self.model.operator = Some(operator);
+                        self.audit(AuditAction::Login, detail);
+                        self.model.login_error = None;
+                        self.model.state = TestState::Ready;
+                        text_input::focus(text_input::Id::new("0"))
+                    }
+                    None => {
+                        self.audit(AuditAction::Login, "credenziali non riconosciute");
+                        self.model.login_error = Some(String::from("Credenziali non riconosciute"));
+                        text_input::focus(text_input::Id::new(view::LOGIN_INPUT))
+                    }
                 }
-                text_input::focus(text_input::Id::new("0"))
+            }
+            Event::ViewEvent(view::Event::Logout) => {
+                if let TestState::Ready = self.model.state {
+                    if let Some(operator) = self.model.operator.clone() {
+                        self.model.log(format!("Uscita di {}", operator.nome));
+                        self.audit(AuditAction::Logout, operator.nome);
+                        self.model.operator = None;
+                    }
+                    self.model.state = TestState::Login;
+                }
+                text_input::focus(text_input::Id::new(view::LOGIN_INPUT))
             }
             Event::ViewEvent(view::Event::BarcodeRead(index, val)) => {
                 self.model
@@ -256,12 +572,138 @@ impl Application for App {
                 text_input::focus(text_input::Id::new("0"))
             }
 
-            Event::ViewEvent(view::Event::Start) => self.start_procedure(),
+            Event::ViewEvent(view::Event::Start) => {
+                if self.model.operator.is_none() || self.model.calibration.is_none() {
+                    return Command::none();
+                }
+                // Records since the last run, collected on entering `update`, belong to no run
+                self.model.run_log.clear();
+                reles::record_insertion();
+                let command = self.start_procedure();
+                let detail = format!("variante {}", self.model.report.barcode.variante);
+                self.audit(AuditAction::Start, detail);
+                command
+            }
+            Event::ViewEvent(view::Event::Maintenance) => {
+                if !self.model.allowed(Permission::Maintenance) {
+                    return Command::none();
+                }
+                self.model.maintenance = reles::counters();
+                self.model.limit_inputs = TestStep::ALL
+                    .iter()
+                    .filter_map(|step| {
+                        let (min, max) = step.limits()?;
+                        Some((*step, min.to_string(), max.to_string()))
+                    })
+                    .collect();
+                self.model.limits_result = None;
+                self.model.state = TestState::Maintenance;
+                Command::none()
+            }
+            Event::ViewEvent(view::Event::MaintenanceReset(rele)) => {
+                if !self.model.allowed(Permission::Maintenance) {
+                    return Command::none();
+                }
+                let counter = rele.as_deref().unwrap_or("inserimenti");
+                self.audit(
+                    AuditAction::ConfigChange,
+                    format!("azzerato contatore {}", counter),
+                );
+                reles::reset_counter(rele.as_deref());
+                self.model.maintenance = reles::counters();
+                Command::none()
+            }
+            Event::ViewEvent(view::Event::CalibrateFrequency) => {
+                if !self.model.allowed(Permission::Calibration) {
+                    return Command::none();
+                }
+                self.model.calibration_result = None;
+                Command::perform(
+                    frequency::calibrate(self.model.config.pin_loopback_pwm),
+                    Event::CalibrationDone,
+                )
+            }
+            Event::CalibrationDone(result) => {
+                if let Ok(correction) = result {
+                    self.model.calibration = Some(Calibration {
+                        correzione_frequenza: correction,
+                    });
+                    let detail = format!("correzione frequenza {:.4}", correction);
+                    self.audit(AuditAction::ConfigChange, detail);
+                }
+                self.model.calibration_result = Some(result);
+                Command::none()
+            }
+            Event::ViewEvent(view::Event::LimitEdited(index, maximum, value)) => {
+                if let Some(input) = self.model.limit_inputs.get_mut(index) {
+                    if maximum {
+                        input.2 = value;
+                    } else {
+                        input.1 = value;
+                    }
+                }
+                Command::none()
+            }
+            Event::ViewEvent(view::Event::SaveLimits) => {
+                if !self.model.allowed(Permission::Limits) {
+                    return Command::none();
+                }
+                self.model.limits_result = Some(self.save_limits());
+                Command::none()
+            }
+            Event::ViewEvent(view::Event::MaintenanceBack) => {
+                self.model.state = TestState::Ready;
+                text_input::focus(text_input::Id::new("0"))
+            }
+            Event::ViewEvent(view::Event::SelfTest) => self.start_self_test(),
+            Event::SelfTestDone(checks) => {
+                let failed: Vec<&FixtureCheck> = checks.iter().filter(|c| !c.passed).collect();
+
+                if failed.is_empty() {
+                    self.model.log("Autotest attrezzatura superato");
+                    self.model.state = if self.model.operator.is_some() {
+                        TestState::Ready
+                    } else {
+                        TestState::Login
+                    };
+                } else {
+                    for check in &failed {
+                        let msg = format!("Autotest {}: {}", check.name, check.detail);
+                        self.model.log(msg);
+                    }
+                    self.model.state = TestState::FixtureFault;
+                }
+
+                self.model.fixture_checks = checks;
+                if let TestState::Login = self.model.state {
+                    text_input::focus(text_input::Id::new(view::LOGIN_INPUT))
+                } else {
+                    text_input::focus(text_input::Id::new("0"))
+                }
+            }
+            Event::ViewEvent(view::Event::Skip) => match self.model.state {
+                TestState::Testing(step, StepState::Failed)
+                    if step.skippable() && self.model.allowed(Permission::SkipStep) =>
+                {
+                    self.model
+                        .log(format!("{}: prova saltata", step.metadata().1));
+                    self.audit(AuditAction::Skip, step.metadata().1);
+                    self.next_step(step)
+                }
+                _ => Command::none(),
+            },
             Event::ViewEvent(view::Event::Retry) => {
+                if !self.model.allowed(Permission::Retry) {
+                    return Command::none();
+                }
+                if let TestState::Testing(step, _) = self.model.state {
+                    self.audit(AuditAction::Retry, step.metadata().1);
+                }
                 use TestStep::*;
                 match self.model.state {
                     TestState::Testing(FlashingTest, _) => self.flash_test_firmware(),
                     TestState::Testing(Connecting, _) => {
+                        self.model.report.device = None;
                         self.controller_message(ControllerMessage::Connect(PORT.into()));
                         Command::none()
                     }
@@ -270,14 +712,26 @@ impl Application for App {
                             TestState::Testing(TestStep::InvertPower, StepState::Waiting);
                         Self::perform_power_inversion()
                     }
-                    TestState::Testing(Check3v3 | Check5v | Check12v, _) => {
-                        if self.test_power().is_ok() {
-                            self.start_test(AnalogShortCircuit)
+                    TestState::Testing(CheckVRef, _) => self.start_procedure(),
+                    TestState::Testing(step @ (UiLeftButton | UiRightButton), _) => {
+                        self.start_button_test(step)
+                    }
+                    TestState::Testing(UiLCD, _) => self.start_lcd_test(),
+                    TestState::Testing(
+                        step @ (UiRgbWhite | UiRgbRed | UiRgbGreen | UiRgbBlue),
+                        _,
+                    ) => self.start_rgb_test(step),
+                    TestState::Testing(Check3v3 | Check5v | Check12v | Pressure, _) => {
+                        if self.test_power().is_ok() && self.test_pressure().is_ok() {
+                            self.start_test(CurrentIdle)
                         } else {
                             iced::widget::scrollable::snap_to(Id::new("logs"), RelativeOffset::END)
                         }
                     }
                     TestState::Testing(FlashingProduction, _) => self.flash_production_firmware(),
+                    TestState::Testing(WriteManufacturingData, _) => self.write_manufacturing_data(),
+                    TestState::Testing(OptionBytes, _) => self.write_option_bytes(),
+                    TestState::Testing(VerifyProduction, _) => self.verify_production_firmware(),
                     TestState::Testing(step, _) => {
                         self.model.state = TestState::Testing(step, StepState::Waiting);
                         self.controller_message(ControllerMessage::Test(step));
@@ -287,7 +741,22 @@ impl Application for App {
                 }
             }
             Event::ViewEvent(view::Event::UiFail) => {
+                if let Some(detail) = self.manual_check() {
+                    self.audit(AuditAction::Reject, detail);
+                }
                 match self.model.state {
+                    TestState::Testing(step, _) if step.light().is_some() => {
+                        return self.backlight_result(step, false);
+                    }
+                    TestState::Testing(TestStep::UiLCD, _) => {
+                        let pattern = self.model.lcd_pattern();
+                        self.model
+                            .log(format!("Pattern LCD non corretto: {}", pattern.description()));
+                        let normal = ControllerMessage::SetLcdPattern(LcdPattern::Normal);
+                        self.controller_message(normal);
+                        self.add_test(TestStep::UiLCD, false, None);
+                        self.model.state = TestState::Done;
+                    }
                     TestState::Testing(step, _) => {
                         self.add_test(step, false, None);
                         self.model.state = TestState::Done;
@@ -296,26 +765,55 @@ impl Application for App {
                 }
                 Command::none()
             }
-            Event::ViewEvent(view::Event::UiOk) => match self.model.state {
-                TestState::Testing(TestStep::UiLCD, _) => {
-                    self.model.log("LCD funzionante");
-                    self.add_test(TestStep::UiLCD, true, None);
-                    self.next_step(TestStep::UiLCD)
-                }
-                _ => {
-                    self.model.log("RGB funzionante");
-                    self.add_test(TestStep::UiRgb, true, None);
-                    self.next_step(TestStep::UiRgb)
+            Event::ViewEvent(view::Event::UiOk) => {
+                if let Some(detail) = self.manual_check() {
+                    self.audit(AuditAction::Confirm, detail);
                 }
-            },
-            Event::ViewEvent(view::Event::Done) => {
-                save_report(&self.model);
+                match self.model.state {
+                    TestState::Testing(TestStep::UiLCD, _) => {
+                        self.model.lcd_pattern += 1;
+                        if self.model.lcd_pattern < LcdPattern::sequence().len() {
+                            let pattern = self.model.lcd_pattern();
+                            self.controller_message(ControllerMessage::SetLcdPattern(pattern));
+                            return Command::none();
+                        }
 
-                self.model.logs = vec![];
-                self.model.state = TestState::Ready;
+                        self.model.log("LCD funzionante");
+                        let normal = ControllerMessage::SetLcdPattern(LcdPattern::Normal);
+                        self.controller_message(normal);
+                        self.add_test(TestStep::UiLCD, true, None);
+                        self.next_step(TestStep::UiLCD)
+                    }
+                    TestState::Testing(step, _) if step.light().is_some() => {
+                        self.backlight_result(step, true)
+                    }
+                    _ => Command::none(),
+                }
+            }
+            Event::Shutdown => {
+                logging::info("app", "Chiusura", &[]);
                 self.controller_message(ControllerMessage::Disconnect);
                 reles::all_off();
-                self.model.report = Report::default();
+                reles::save_counters();
+                iced::window::close()
+            }
+            Event::ViewEvent(view::Event::Done) => {
+                match self.model.state {
+                    TestState::Testing(step, _) => {
+                        self.audit(AuditAction::Abort, step.metadata().1);
+                    }
+                    TestState::Done => {
+                        let outcome = if self.model.report.successful() {
+                            "Pass"
+                        } else {
+                            "Fail"
+                        };
+                        self.audit(AuditAction::Done, outcome);
+                    }
+                    _ => (),
+                }
+                self.conclude_run();
+                self.model.state = TestState::Ready;
 
                 text_input::focus(text_input::Id::new("0"))
             }
@@ -328,10 +826,15 @@ impl Application for App {
         let mut subscriptions = vec![
             worker::worker().map(Event::ControllerEvent),
             every(Duration::from_millis(200)).map(Event::UpdateVBat),
+            shutdown(),
         ];
 
         match self.model.state {
-            TestState::Testing(TestStep::UiRgb, _) => {
+            TestState::Testing(step, StepState::Waiting)
+                if step.light().is_some()
+                    && self.model.config.sensore_colore.is_none()
+                    && self.model.config.livelli_retroilluminazione.len() > 1 =>
+            {
                 subscriptions.push(every(Duration::from_millis(1000)).map(Event::UpdateLight));
             }
             _ => (),
@@ -358,6 +861,62 @@ impl App {
         Command::none()
     }
 
+    fn test_vref(self: &mut Self) -> Result<(), ()> {
+        let vref = worker::read_vref();
+        self.model.vref = vref.ok();
+
+        let vref = match vref {
+            Ok(vref) => vref,
+            Err(()) => {
+                logging::error("app", "Lettura della tensione di riferimento fallita", &[]);
+                self.add_test(TestStep::CheckVRef, false, None);
+                self.model.state = TestState::Testing(TestStep::CheckVRef, StepState::Failed);
+                return Err(());
+            }
+        };
+        self.model
+            .log(format!("Tensione di riferimento: {}V", vref));
+
+        let success = TestStep::CheckVRef.check_limits(vref);
+        self.add_test(TestStep::CheckVRef, success, Some(vref));
+
+        if success {
+            Ok(())
+        } else {
+            self.model.state = TestState::Testing(TestStep::CheckVRef, StepState::Failed);
+            Err(())
+        }
+    }
+
+    fn test_pressure(self: &mut Self) -> Result<(), ()> {
+        let variant = self.model.report.barcode.variante.trim().to_string();
+        if !self.model.config.varianti_pressione.contains(&variant) {
+            return Ok(());
+        }
+
+        let pressure = match worker::read_pressure() {
+            Ok(pressure) => pressure,
+            Err(()) => {
+                logging::error("app", "Lettura dell'ingresso pressione fallita", &[]);
+                self.add_test(TestStep::Pressure, false, None);
+                self.model.state = TestState::Testing(TestStep::Pressure, StepState::Failed);
+                return Err(());
+            }
+        };
+        self.model
+            .log(format!("Tensione su ingresso pressione: {}V", pressure));
+
+        let success = TestStep::Pressure.check_limits(pressure);
+        self.add_test(TestStep::Pressure, success, Some(pressure));
+
+        if success {
+            Ok(())
+        } else {
+            self.model.state = TestState::Testing(TestStep::Pressure, StepState::Failed);
+            Err(())
+        }
+    }
+
     fn test_power(self: &mut Self) -> Result<(), ()> {
         let power3v3 = adc::read_adc(adc::Channel::Volt3).map_err(|_| ())?;
         let power3v3 = ((power3v3 as f64 / 4095.0) * 3.35) * 2.0;
@@ -379,7 +938,7 @@ impl App {
             ));
         } else {
             let power5v = adc::read_adc(adc::Channel::Volt5).map_err(|_| ())?;
-            println!("{}", power5v);
+            logging::debug("app", "Lettura 5v", &[("adc", &power5v)]);
             let power5v = ((power5v as f64 / 4095.0) * 3.35) * 2.0;
             let power5v = (power5v * 100.0).round() / 100.0;
 
@@ -412,6 +971,24 @@ impl App {
         }
     }
 
+    fn check_hardware_revision(&mut self) -> bool {
+        let rev_hw = self.model.report.barcode.rev_hw.clone();
+
+        match &self.model.report.device {
+            Some(info) if info.matches_revision(rev_hw.as_str()) => true,
+            Some(info) => {
+                let revision = info.hardware_revision;
+                self.model.log(format!(
+                    "Revisione hardware {} diversa da quella indicata ({})",
+                    revision, rev_hw
+                ));
+                self.controller_message(ControllerMessage::Disconnect);
+                false
+            }
+            None => false,
+        }
+    }
+
     fn next_step(&mut self, step: TestStep) -> Command<Event> {
         self.start_ts = Instant::now();
 
@@ -420,39 +997,53 @@ impl App {
             InvertPower => self.flash_test_firmware(),
             FlashingTest => {
                 self.model.state = TestState::Testing(Connecting, StepState::Waiting);
+                self.model.report.device = None;
+                self.model.digiblock_state = DigiblockState::default();
                 self.controller_message(ControllerMessage::Connect(String::from(PORT)));
                 Command::none()
             }
-            Connecting => {
-                self.model.state = TestState::Testing(UiLeftButton, StepState::Waiting);
-                Command::none()
-            }
-            UiLeftButton => {
-                self.model.state = TestState::Testing(UiRightButton, StepState::Waiting);
-                Command::none()
-            }
-            UiRightButton => {
-                self.model.state = TestState::Testing(UiLCD, StepState::Waiting);
-                Command::none()
-            }
-            UiLCD => {
-                self.model.state = TestState::Testing(UiRgb, StepState::Waiting);
-                self.model.light = RgbLight::default();
-                Command::none()
-            }
-            UiRgb => {
-                if self.test_power().is_ok() {
-                    self.start_test(AnalogShortCircuit)
+            Connecting => self.start_button_test(UiLeftButton),
+            UiLeftButton => self.start_button_test(UiRightButton),
+            UiRightButton => self.start_lcd_test(),
+            UiLCD => self.start_rgb_test(UiRgbWhite),
+            UiRgbWhite => self.start_rgb_test(UiRgbRed),
+            UiRgbRed => self.start_rgb_test(UiRgbGreen),
+            UiRgbGreen => self.start_rgb_test(UiRgbBlue),
+            UiRgbBlue => {
+                if self.test_power().is_ok() && self.test_pressure().is_ok() {
+                    self.start_test(CurrentIdle)
                 } else {
                     Command::none()
                 }
             }
+            CurrentIdle => self.start_test(CurrentWhite),
+            CurrentWhite => self.start_test(CurrentRed),
+            CurrentRed => self.start_test(CurrentGreen),
+            CurrentGreen => self.start_test(CurrentBlue),
+            CurrentBlue => self.start_test(CurrentOutput),
+            CurrentOutput => self.start_test(AnalogShortCircuit),
             AnalogShortCircuit => self.start_test(Analog),
             Analog => self.start_test(Frequency),
-            Frequency => self.start_test(OutputShortCircuit),
+            Frequency => self.start_test(Pulses),
+            Pulses => self.start_test(OutputShortCircuit),
             OutputShortCircuit => self.start_test(Output),
-            Output => self.flash_production_firmware(),
+            Output => self.write_manufacturing_data(),
+            WriteManufacturingData => self.flash_production_firmware(),
             FlashingProduction => {
+                let options = self
+                    .model
+                    .production_firmware()
+                    .and_then(|image| image.option_bytes.clone());
+
+                if options.is_some() {
+                    self.write_option_bytes()
+                } else {
+                    self.after_production_firmware()
+                }
+            }
+            OptionBytes => self.after_production_firmware(),
+            Check3v3 | Check5v | Check12v | Pressure => self.start_test(CurrentIdle),
+            VerifyProduction => {
                 self.model.state = TestState::Done;
                 Command::none()
             }
@@ -460,6 +1051,202 @@ impl App {
         }
     }
 
+    /// Waits for the button of `step` to be pressed, failing at once if a button is already
+    /// pressed before the prompt is shown
+    fn start_button_test(self: &mut Self, step: TestStep) -> Command<Event> {
+        self.start_ts = Instant::now();
+
+        let state = &self.model.digiblock_state;
+        let stuck = match step {
+            TestStep::UiLeftButton => [
+                ("sinistro", state.left_button),
+                ("destro", state.right_button),
+            ]
+            .into_iter()
+            .find(|(_, pressed)| *pressed),
+            _ => Some(("destro", state.right_button)).filter(|(_, pressed)| *pressed),
+        };
+
+        if let Some((name, _)) = stuck {
+            self.model.log(format!("Tasto {} bloccato premuto", name));
+            self.add_test(step, false, None);
+            self.model.state = TestState::Testing(step, StepState::Failed);
+            return Command::none();
+        }
+
+        self.model.state = TestState::Testing(step, StepState::Waiting);
+
+        if self.model.config.attuatori_tasti {
+            let actuator = match step {
+                TestStep::UiLeftButton => reles::Rele::LeftButtonActuator,
+                _ => reles::Rele::RightButtonActuator,
+            };
+            self.controller_message(ControllerMessage::PressButton(actuator));
+        }
+
+        Command::none()
+    }
+
+    /// Checks the LCD with the camera if the fixture has one, otherwise asks the operator to
+    /// confirm each test pattern in turn
+    fn start_lcd_test(self: &mut Self) -> Command<Event> {
+        self.start_ts = Instant::now();
+        self.model.state = TestState::Testing(TestStep::UiLCD, StepState::Waiting);
+
+        if let Some(camera) = self.model.config.camera.clone() {
+            self.model.report.lcd_segments.clear();
+            self.controller_message(ControllerMessage::LcdCheck(camera));
+        } else {
+            self.model.lcd_pattern = 0;
+            let pattern = self.model.lcd_pattern();
+            self.controller_message(ControllerMessage::SetLcdPattern(pattern));
+        }
+
+        Command::none()
+    }
+
+    /// Checks a backlight colour with the colour sensor if the fixture has one, otherwise shows
+    /// it to the operator cycling through the intensity levels
+    fn start_rgb_test(self: &mut Self, step: TestStep) -> Command<Event> {
+        let light = step.light().unwrap_or_default();
+
+        self.start_ts = Instant::now();
+        self.model.state = TestState::Testing(step, StepState::Waiting);
+        self.model.light_level = 0;
+
+        if let Some(kind) = self.model.config.sensore_colore {
+            self.model
+                .report
+                .color_readings
+                .retain(|r| r.light.step() != step);
+            self.controller_message(ControllerMessage::ColorCheck(
+                light,
+                kind,
+                self.model.config.livelli_retroilluminazione.clone(),
+                self.model.config.intensita_minima,
+            ));
+        } else {
+            let intensity = self.model.light_intensity();
+            self.controller_message(ControllerMessage::SetLight(light, intensity));
+        }
+
+        Command::none()
+    }
+
+    /// Records the result of a backlight colour and goes on with the next one even if it failed,
+    /// so that the report shows every faulty channel
+    fn backlight_result(self: &mut Self, step: TestStep, success: bool) -> Command<Event> {
+        self.add_test(step, success, None);
+
+        if step != TestStep::UiRgbBlue {
+            return self.next_step(step);
+        }
+
+        let failed = RgbLight::ALL.iter().any(|light| {
+            self.model
+                .report
+                .tests
+                .get(&light.step())
+                .map(|result| !result.success)
+                .unwrap_or(false)
+        });
+
+        if failed {
+            self.model.state = TestState::Done;
+            Command::none()
+        } else {
+            self.next_step(step)
+        }
+    }
+
+    /// Validates the limits typed on the maintenance screen and keeps those differing from the
+    /// default ones
+    fn save_limits(self: &mut Self) -> Result<(), String> {
+        let mut overrides = BTreeMap::new();
+        let mut changes = Vec::new();
+
+        for (step, min, max) in &self.model.limit_inputs {
+            let code = step.metadata().0;
+            let parse = |value: &String| {
+                value
+                    .trim()
+                    .replace(',', ".")
+                    .parse::<f64>()
+                    .map_err(|_| format!("{}: valore non valido \"{}\"", code, value))
+            };
+            let entered = StepLimits {
+                minimo: parse(min)?,
+                massimo: parse(max)?,
+            };
+            if entered.minimo > entered.massimo {
+                return Err(format!("{}: minimo maggiore del massimo", code));
+            }
+
+            if step.limits() != Some((entered.minimo, entered.massimo)) {
+                changes.push(format!("{} {}-{}", code, entered.minimo, entered.massimo));
+            }
+            if step.default_limits() != Some((entered.minimo, entered.massimo)) {
+                overrides.insert(String::from(code), entered);
+            }
+        }
+
+        limits::save(overrides)?;
+        if !changes.is_empty() {
+            self.audit(
+                AuditAction::ConfigChange,
+                format!("limiti {}", changes.join(", ")),
+            );
+        }
+        Ok(())
+    }
+
+    /// Records an operator action in the audit log and, during a run, in the report
+    fn audit(self: &mut Self, action: AuditAction, detail: impl Into<String>) {
+        let running = matches!(self.model.state, TestState::Testing(..) | TestState::Done);
+        let entry = AuditEntry {
+            time: chrono::Local::now(),
+            operator: self
+                .model
+                .operator
+                .as_ref()
+                .map(|o| o.code())
+                .unwrap_or_default(),
+            serial: if running {
+                self.model.report.barcode.matricola.clone()
+            } else {
+                String::new()
+            },
+            action,
+            detail: detail.into(),
+        };
+
+        audit::append(&entry);
+        if running {
+            self.model.report.audit.push(entry);
+        }
+    }
+
+    /// Describes the manual check the operator is answering, `None` if the current step is
+    /// checked by the fixture
+    fn manual_check(self: &Self) -> Option<String> {
+        let step = match self.model.state {
+            TestState::Testing(step, _) => step,
+            _ => return None,
+        };
+        match step {
+            TestStep::UiLeftButton | TestStep::UiRightButton => Some(step.metadata().1.into()),
+            TestStep::UiLCD if self.model.config.camera.is_none() => Some(format!(
+                "{}: {}",
+                step.metadata().1,
+                self.model.lcd_pattern().description()
+            )),
+            _ if step.light().is_some() && self.model.config.sensore_colore.is_none() => Some(
+                format!("{} al {}%", step.metadata().1, self.model.light_intensity()),
+            ),
+            _ => None,
+        }
+    }
+
     fn add_test(self: &mut Self, step: TestStep, result: bool, value: Option<f64>) {
         self.model.report.add_test(TestStepResult::new(
             step,
@@ -471,27 +1258,15 @@ impl App {
 
     fn flash_test_firmware(self: &mut Self) -> Command<Event> {
         self.model.state = TestState::Testing(TestStep::FlashingTest, StepState::Waiting);
-        Command::perform(
-            async {
-                let res = flashing::load_test_firmware().await;
-                if res == Some(0) {
-                    worker::reset().await;
-                }
-                res
-            },
-            |res| {
-                ControllerEvent::TestResult(
-                    TestStep::FlashingTest,
-                    None,
-                    if let Some(code) = res {
-                        code == 0
-                    } else {
-                        false
-                    },
-                )
-            },
-        )
-        .map(Event::ControllerEvent)
+
+        let image = if let Some(firmware) = &self.model.firmware {
+            firmware.collaudo.clone()
+        } else {
+            return Self::firmware_missing(TestStep::FlashingTest);
+        };
+
+        self.flash(TestStep::FlashingTest, image);
+        Command::none()
     }
 
     fn flash_production_firmware(self: &mut Self) -> Command<Event> {
@@ -499,27 +1274,119 @@ impl App {
 
         self.model.state = TestState::Testing(TestStep::FlashingProduction, StepState::Waiting);
 
+        let image = if let Some(image) = self.model.production_firmware() {
+            image.clone()
+        } else {
+            self.model.log(format!(
+                "Nessun firmware di produzione per la variante {}",
+                self.model.report.barcode.variante
+            ));
+            return Self::firmware_missing(TestStep::FlashingProduction);
+        };
+
+        // The worker handles messages in order, so it is disconnected by the time it flashes
+        self.flash(TestStep::FlashingProduction, image);
+        Command::none()
+    }
+
+    fn flash(self: &mut Self, step: TestStep, image: FirmwareImage) {
+        self.model.flashing = Some(FlashingProgress::new());
+        self.controller_message(ControllerMessage::Flash(
+            step,
+            image,
+            self.model.config.programmatore,
+            self.model.config.chip.clone(),
+        ));
+    }
+
+    fn update_flashing_time(self: &mut Self, step: TestStep) {
+        let elapsed = if let Some(flashing) = self.model.flashing.take() {
+            flashing.start.elapsed().as_secs_f64()
+        } else {
+            return;
+        };
+
+        // Moving average, so the estimate follows slow changes of the fixture
+        let code = String::from(step.metadata().0);
+        let average = match self.model.flashing_times.get(&code) {
+            Some(previous) => previous * 0.8 + elapsed * 0.2,
+            None => elapsed,
+        };
+        self.model.flashing_times.insert(code, average);
+
+        if let Ok(content) = serde_yaml::to_string(&self.model.flashing_times) {
+            fs::write(FLASHING_TIMES, content).ok();
+        }
+    }
+
+    fn write_manufacturing_data(self: &mut Self) -> Command<Event> {
+        self.model.state =
+            TestState::Testing(TestStep::WriteManufacturingData, StepState::Waiting);
+        self.controller_message(ControllerMessage::WriteManufacturingData(
+            self.model.report.barcode.clone(),
+        ));
+        Command::none()
+    }
+
+    fn after_production_firmware(self: &mut Self) -> Command<Event> {
+        if self.model.config.verifica_firmware_produzione {
+            self.verify_production_firmware()
+        } else {
+            self.model.state = TestState::Done;
+            Command::none()
+        }
+    }
+
+    fn write_option_bytes(self: &mut Self) -> Command<Event> {
+        self.model.state = TestState::Testing(TestStep::OptionBytes, StepState::Waiting);
+
+        let options = self
+            .model
+            .production_firmware()
+            .and_then(|image| image.option_bytes.clone());
+
+        if let Some(options) = options {
+            self.controller_message(ControllerMessage::OptionBytes(
+                options,
+                self.model.config.programmatore,
+                self.model.config.chip.clone(),
+            ));
+            Command::none()
+        } else {
+            Self::firmware_missing(TestStep::OptionBytes)
+        }
+    }
+
+    fn verify_production_firmware(self: &mut Self) -> Command<Event> {
+        self.model.state = TestState::Testing(TestStep::VerifyProduction, StepState::Waiting);
+
+        let expected = self
+            .model
+            .production_firmware()
+            .map(|image| image.versione.clone())
+            .unwrap_or_default();
+        self.model
+            .log(format!("Verifica firmware di produzione {}", expected));
+
         Command::perform(
-            async {
-                tokio::time::sleep(Duration::from_millis(500)).await;
-                // Shitty busy loop to make sure we wait for disconnection
-                flashing::load_production_firmware().await
-            },
-            |res| {
+            flashing::verify_production_firmware(String::from(PORT)),
+            move |found| {
                 ControllerEvent::TestResult(
-                    TestStep::FlashingProduction,
+                    TestStep::VerifyProduction,
                     None,
-                    if let Some(code) = res {
-                        code == 0
-                    } else {
-                        false
-                    },
+                    found.map(|v| v == expected).unwrap_or(false),
                 )
             },
         )
         .map(Event::ControllerEvent)
     }
 
+    fn firmware_missing(step: TestStep) -> Command<Event> {
+        Command::perform(async {}, move |_| {
+            Event::ControllerEvent(ControllerEvent::TestResult(step, None, false))
+        })
+    }
+
     fn perform_power_inversion() -> Command<Event> {
         Command::perform(worker::check_power_inversion(), |r| {
             let (_value, success) = if let Ok(value) = r {
@@ -536,9 +1403,72 @@ impl App {
         })
     }
 
+    /// Saves the report of the run and releases the board
+    fn conclude_run(self: &mut Self) {
+        save_report(&self.model);
+
+        self.model.logs = vec![];
+        self.model.run_log = vec![];
+        self.controller_message(ControllerMessage::Disconnect);
+        reles::all_off();
+        reles::save_counters();
+        self.model.maintenance = reles::counters();
+        self.model.report = Report::default();
+    }
+
+    fn start_self_test(self: &mut Self) -> Command<Event> {
+        self.model.state = TestState::SelfTest;
+        Command::perform(
+            selftest::run(self.model.config.clone()),
+            Event::SelfTestDone,
+        )
+    }
+
     fn start_procedure(self: &mut Self) -> Command<Event> {
+        self.start_ts = Instant::now();
+        self.model.state = TestState::Testing(TestStep::CheckVRef, StepState::Waiting);
+
+        if self.test_vref().is_err() {
+            self.model.state = TestState::Testing(TestStep::CheckVRef, StepState::Failed);
+            return iced::widget::scrollable::snap_to(Id::new("logs"), RelativeOffset::END);
+        }
+
         self.start_ts = Instant::now();
         self.model.state = TestState::Testing(TestStep::InvertPower, StepState::Waiting);
         Self::perform_power_inversion()
     }
 }
+
+/// Window close requests and SIGTERM, both of which must leave the fixture in a safe state
+fn shutdown() -> iced::Subscription<Event> {
+    use iced::futures::SinkExt;
+    use iced_native::{subscription, window};
+    use tokio::signal::unix::{signal, SignalKind};
+
+    struct Terminate;
+
+    let close = subscription::events_with(|event, _| match event {
+        iced::Event::Window(window::Event::CloseRequested) => Some(Event::Shutdown),
+        _ => None,
+    });
+
+    let terminate = subscription::channel(
+        std::any::TypeId::of::<Terminate>(),
+        1,
+        |mut output| async move {
+            let mut terminate = signal(SignalKind::terminate()).ok();
+
+            loop {
+                match terminate.as_mut() {
+                    Some(terminate) => {
+                        terminate.recv().await;
+                        output.send(Event::Shutdown).await.ok();
+                    }
+                    None => std::future::pending::<()>().await,
+                }
+            }
+        },
+    );
+
+    iced::Subscription::batch(vec![close, terminate])
+}
@@ -0,0 +1,68 @@
+// Append-only log of the operator actions, kept across restarts
+use std::fs::OpenOptions;
+use std::io::prelude::*;
+
+use crate::logging;
+use crate::model::AuditEntry;
+
+const AUDIT_LOG: &str = "./audit.yaml";
+
+/// Formats the entry as an item of a YAML list, so that the whole log stays valid YAML
+pub fn yaml(entry: &AuditEntry) -> String {
+    format!(
+        r#"- data: '{}'
+  operatore: {}
+  matricola: {}
+  azione: '{}'
+  dettaglio: {}
+"#,
+        entry.time.format("%Y-%m-%d %H:%M:%S"),
+        quote(&entry.operator),
+        quote(&entry.serial),
+        entry.action.name(),
+        quote(&entry.detail),
+    )
+}
+
+/// Quotes a free text value as a YAML scalar: single quoted when it fits on one line, double
+/// quoted with escapes when it holds line breaks or other control characters
+fn quote(value: &str) -> String {
+    if !value.chars().any(char::is_control) {
+        return format!("'{}'", value.replace('\'', "''"));
+    }
+
+    let mut quoted = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '"' => quoted += "\\\"",
+            '\\' => quoted += "\\\\",
+            '\n' => quoted += "\\n",
+            '\r' => quoted += "\\r",
+            '\t' => quoted += "\\t",
+            c if c.is_control() => quoted += format!("\\u{:04x}", c as u32).as_str(),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Appends the entry to the log, flushing it to disk right away
+pub fn append(entry: &AuditEntry) {
+    let result = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(AUDIT_LOG)
+        .and_then(|mut file| {
+            file.write_all(yaml(entry).as_bytes())?;
+            file.sync_data()
+        });
+
+    if let Err(e) = result {
+        logging::error(
+            "audit",
+            "Impossibile scrivere il registro azioni",
+            &[("errore", &e)],
+        );
+    }
+}
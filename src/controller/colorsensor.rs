@@ -0,0 +1,136 @@
+// TCS34725 colour sensor facing the backlight of the board
+use std::thread;
+use std::time::Duration;
+
+use rppal::i2c::I2c;
+
+use crate::model::{ColorReading, ColorSensorKind, RgbLight};
+
+const ADDRESS: u16 = 0x29;
+
+/// Command bit, with auto increment of the register address
+const COMMAND: u8 = 0x80;
+const AUTO_INCREMENT: u8 = 0x20;
+
+const REGISTER_ENABLE: u8 = 0x00;
+const REGISTER_ATIME: u8 = 0x01;
+const REGISTER_CONTROL: u8 = 0x0F;
+const REGISTER_ID: u8 = 0x12;
+const REGISTER_STATUS: u8 = 0x13;
+const REGISTER_CDATAL: u8 = 0x14;
+
+const ENABLE_PON: u8 = 0x01;
+const ENABLE_AEN: u8 = 0x02;
+const STATUS_AVALID: u8 = 0x01;
+
+/// 43 integration cycles, about 100ms
+const ATIME_100MS: u8 = 0xD5;
+/// Counts reached by a saturated channel: 1024 per integration cycle, at most 65535
+const SATURATION: u32 = (256 - ATIME_100MS as u32) * 1024;
+const INTEGRATION_TIME: Duration = Duration::from_millis(105);
+/// 4x gain
+const CONTROL_GAIN_4X: u8 = 0x01;
+
+const IDS: [u8; 2] = [0x44, 0x4D];
+
+/// Reads the light currently shown by the board, which was commanded to show `light` at
+/// `intensity` percent
+pub fn read(kind: ColorSensorKind, light: RgbLight, intensity: u8) -> Result<ColorReading, String> {
+    let (clear, red, green, blue) = match kind {
+        ColorSensorKind::Tcs34725 => read_tcs34725()?,
+        ColorSensorKind::Simulato => simulated(light, intensity),
+    };
+
+    Ok(ColorReading {
+        light,
+        intensity,
+        red,
+        green,
+        blue,
+        clear,
+        lux: lux(red, green, blue),
+        passed: false,
+    })
+}
+
+/// Checks the reading against the chromaticity of its colour and the minimum intensity, given
+/// at full brightness
+pub fn check(reading: &mut ColorReading, minimum_clear: u16) {
+    let minimum_clear = minimum_clear as u32 * reading.intensity as u32 / 100;
+    let chromaticity = reading.chromaticity();
+    reading.passed = reading.clear as u32 >= minimum_clear
+        && chromaticity
+            .iter()
+            .zip(reading.light.chromaticity_limits())
+            .all(|(value, (min, max))| *value >= min && *value <= max);
+}
+
+fn read_tcs34725() -> Result<(u16, u16, u16, u16), String> {
+    let mut i2c = I2c::new().map_err(|e| e.to_string())?;
+    i2c.set_slave_address(ADDRESS).map_err(|e| e.to_string())?;
+
+    let id = i2c
+        .smbus_read_byte(COMMAND | REGISTER_ID)
+        .map_err(|e| e.to_string())?;
+    if !IDS.contains(&id) {
+        return Err(format!("Sensore colore non riconosciuto ({:#04x})", id));
+    }
+
+    let data = integrate(&mut i2c);
+    // Powered down whatever the outcome, it would keep integrating otherwise
+    i2c.smbus_write_byte(COMMAND | REGISTER_ENABLE, 0).ok();
+    let data = data
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| String::from("Nessuna misura valida dal sensore colore"))?;
+
+    let word = |i: usize| u16::from_le_bytes([data[i], data[i + 1]]);
+    let clear = word(0);
+    if clear as u32 >= SATURATION.min(u16::MAX as u32) {
+        return Err(format!("Sensore colore saturato (chiaro {})", clear));
+    }
+
+    Ok((clear, word(2), word(4), word(6)))
+}
+
+/// Runs an integration cycle and returns the raw channels, `None` if it never completed
+fn integrate(i2c: &mut I2c) -> Result<Option<[u8; 8]>, rppal::i2c::Error> {
+    i2c.smbus_write_byte(COMMAND | REGISTER_ATIME, ATIME_100MS)?;
+    i2c.smbus_write_byte(COMMAND | REGISTER_CONTROL, CONTROL_GAIN_4X)?;
+    i2c.smbus_write_byte(COMMAND | REGISTER_ENABLE, ENABLE_PON)?;
+    thread::sleep(Duration::from_millis(3));
+    i2c.smbus_write_byte(COMMAND | REGISTER_ENABLE, ENABLE_PON | ENABLE_AEN)?;
+
+    // Waits for a full integration cycle
+    thread::sleep(INTEGRATION_TIME);
+    for _ in 0..10 {
+        if i2c.smbus_read_byte(COMMAND | REGISTER_STATUS)? & STATUS_AVALID != 0 {
+            let mut data = [0u8; 8];
+            i2c.write_read(&[COMMAND | AUTO_INCREMENT | REGISTER_CDATAL], &mut data)?;
+            return Ok(Some(data));
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+
+    Ok(None)
+}
+
+fn simulated(light: RgbLight, intensity: u8) -> (u16, u16, u16, u16) {
+    let (red, green, blue): (u32, u32, u32) = match light {
+        RgbLight::White => (1200, 1300, 1100),
+        RgbLight::Red => (1500, 150, 120),
+        RgbLight::Green => (200, 1400, 300),
+        RgbLight::Blue => (100, 300, 1300),
+    };
+    let scale = |value: u32| (value * intensity as u32 / 100) as u16;
+    (
+        scale(red + green + blue),
+        scale(red),
+        scale(green),
+        scale(blue),
+    )
+}
+
+/// Illuminance from the colour channels, as in the TCS34725 application note
+fn lux(red: u16, green: u16, blue: u16) -> f64 {
+    (-0.32466 * red as f64 + 1.57837 * green as f64 - 0.73191 * blue as f64).max(0.0)
+}
@@ -2,90 +2,210 @@ use std::time::Duration;
 
 use tokio_modbus::client::Context;
 use tokio_modbus::prelude::*;
+use tokio_modbus::slave::Slave;
+use tokio_serial::SerialStream;
 
-use crate::model::{DigiblockState, RgbLight};
+use crate::logging;
+use crate::model::{Barcode, DigiblockInfo, DigiblockState, LcdPattern, RgbLight};
 
-const INPUT_REGISTER_BTN_TEST: u16 = 0;
-//const INPUT_REGISTER_FREQUENCY: u16 = 1;
-//const INPUT_REGISTER_PULSES: u16 = 2;
-//const INPUT_REGISTER_420MA: u16 = 3;
-const NUM_INPUT_REGISTERS: u16 = 7;
+use super::registers::{
+    holding, holding::manufacturing, input, Register, RegisterBlock, TextRegister,
+};
 
-const HOLDING_REGISTER_MODE: u16 = 0;
-const _HOLDING_REGISTER_RESET_PULSES: u16 = 1;
-const HOLDING_REGISTER_OUTPUT: u16 = 2;
-const HOLDING_REGISTER_BACKLIGHT: u16 = 3;
-//const HOLDING_REGISTER_RGB: u16 = 4;
-
-const DIGITAL_MODE: u16 = 1;
-const ANALOG_MODE: u16 = 2;
+const BAUDRATE: u32 = 115200;
+const SLAVE_ADDRESS: u8 = 0x01;
 
 impl TryFrom<Vec<u16>> for DigiblockState {
     type Error = ();
 
     fn try_from(value: Vec<u16>) -> Result<Self, Self::Error> {
-        if value.len() < NUM_INPUT_REGISTERS as usize {
-            Err(())
-        } else {
-            Ok(DigiblockState {
-                left_button: (value[0] & 0x01) > 0,
-                right_button: (value[0] & 0x02) > 0,
-                period_us: value[1],
-                pulses: value[2],
-                ma420: value[3],
-                short_circuit_adc: value[4] > 0,
-                short_circuit_out: value[6] > 0,
-            })
-        }
+        let get = |register: Register| input::STATE.get(&value, register).ok_or(());
+
+        let buttons = get(input::BUTTONS)?;
+
+        Ok(DigiblockState {
+            left_button: (buttons & 0x01) > 0,
+            right_button: (buttons & 0x02) > 0,
+            period_us: get(input::PERIOD)?,
+            pulses: get(input::PULSES)?,
+            ma420: get(input::MA420)?,
+            short_circuit_adc: get(input::SHORT_CIRCUIT_ADC)? > 0,
+            short_circuit_out: get(input::SHORT_CIRCUIT_OUT)? > 0,
+        })
     }
 }
 
-pub async fn get_state(ctx: &mut Context) -> Result<DigiblockState, ()> {
-    ctx.read_input_registers(INPUT_REGISTER_BTN_TEST, NUM_INPUT_REGISTERS)
+impl TryFrom<Vec<u16>> for DigiblockInfo {
+    type Error = ();
+
+    fn try_from(value: Vec<u16>) -> Result<Self, Self::Error> {
+        let get = |register: Register| input::IDENTITY.get(&value, register).ok_or(());
+
+        let uid = [
+            input::UID_0,
+            input::UID_1,
+            input::UID_2,
+            input::UID_3,
+            input::UID_4,
+            input::UID_5,
+        ]
+        .into_iter()
+        .map(|r| get(r).map(|w| format!("{:04X}", w)))
+        .collect::<Result<String, ()>>()?;
+
+        Ok(DigiblockInfo {
+            firmware_version: format!(
+                "{}.{}.{}",
+                get(input::FIRMWARE_VERSION_MAJOR)?,
+                get(input::FIRMWARE_VERSION_MINOR)?,
+                get(input::FIRMWARE_VERSION_PATCH)?
+            ),
+            hardware_revision: get(input::HARDWARE_REVISION)?,
+            uid,
+        })
+    }
+}
+
+pub async fn connect(port: &str) -> Result<Context, ()> {
+    let builder = tokio_serial::new(port, BAUDRATE);
+    let port = SerialStream::open(&builder).map_err(|_| ())?;
+
+    tokio_modbus::client::rtu::connect_slave(port, Slave(SLAVE_ADDRESS))
         .await
         .map_err(|_| ())
-        .and_then(|bytes| DigiblockState::try_from(bytes))
 }
 
-pub async fn set_light(ctx: &mut Context, light: RgbLight) -> Result<(), ()> {
-    ctx.write_multiple_registers(
-        HOLDING_REGISTER_BACKLIGHT,
-        &[
-            100,
-            match light {
-                RgbLight::White => 6,
-                RgbLight::Red => 7,
-                RgbLight::Green => 2,
-                RgbLight::Blue => 1,
-            } as u16,
-        ],
-    )
-    .await
-    .map_err(|_| ())
+async fn write_registers(ctx: &mut Context, register: Register, values: &[u16]) -> Result<(), ()> {
+    ctx.write_multiple_registers(register.address, values)
+        .await
+        .map_err(|_| ())
 }
 
-pub async fn set_frequency_mode(ctx: &mut Context) -> Result<(), ()> {
-    ctx.write_multiple_registers(HOLDING_REGISTER_MODE, &[DIGITAL_MODE])
+/// Writes the whole `block` with a single request
+async fn write_block<const N: usize>(
+    ctx: &mut Context,
+    block: &RegisterBlock<N>,
+    values: &[(Register, u16)],
+) -> Result<(), ()> {
+    ctx.write_multiple_registers(block.first(), &block.encode(values))
         .await
         .map_err(|_| ())
 }
 
-pub async fn set_analog_mode(ctx: &mut Context) -> Result<(), ()> {
-    ctx.write_multiple_registers(HOLDING_REGISTER_MODE, &[ANALOG_MODE])
+pub async fn get_state(ctx: &mut Context) -> Result<DigiblockState, ()> {
+    ctx.read_input_registers(input::STATE.first(), input::STATE.count())
         .await
         .map_err(|_| ())
+        .and_then(|bytes| DigiblockState::try_from(bytes))
 }
 
-pub async fn _reset_pulses(ctx: &mut Context) -> Result<(), ()> {
-    ctx.write_multiple_registers(_HOLDING_REGISTER_RESET_PULSES, &[1])
+pub async fn get_info(ctx: &mut Context) -> Result<DigiblockInfo, ()> {
+    ctx.read_input_registers(input::IDENTITY.first(), input::IDENTITY.count())
         .await
         .map_err(|_| ())
+        .and_then(|bytes| DigiblockInfo::try_from(bytes))
+}
+
+/// Lights the backlight with `light` at `intensity` percent
+pub async fn set_light(ctx: &mut Context, light: RgbLight, intensity: u8) -> Result<(), ()> {
+    let colour = match light {
+        RgbLight::White => holding::RGB_WHITE,
+        RgbLight::Red => holding::RGB_RED,
+        RgbLight::Green => holding::RGB_GREEN,
+        RgbLight::Blue => holding::RGB_BLUE,
+    };
+    let intensity = holding::BACKLIGHT.raw(intensity.min(100) as f64);
+    write_block(
+        ctx,
+        &holding::LIGHT,
+        &[(holding::BACKLIGHT, intensity), (holding::RGB, colour)],
+    )
+    .await
+}
+
+pub async fn set_backlight_off(ctx: &mut Context) -> Result<(), ()> {
+    write_registers(ctx, holding::BACKLIGHT, &[0]).await
+}
+
+pub async fn set_frequency_mode(ctx: &mut Context) -> Result<(), ()> {
+    write_registers(ctx, holding::MODE, &[holding::DIGITAL_MODE]).await
+}
+
+pub async fn set_analog_mode(ctx: &mut Context) -> Result<(), ()> {
+    write_registers(ctx, holding::MODE, &[holding::ANALOG_MODE]).await
+}
+
+pub async fn reset_pulses(ctx: &mut Context) -> Result<(), ()> {
+    write_registers(ctx, holding::RESET_PULSES, &[1]).await
 }
 
 pub async fn set_output(ctx: &mut Context, value: bool) -> Result<(), ()> {
-    ctx.write_multiple_registers(HOLDING_REGISTER_OUTPUT, &[if value { 1 } else { 0 }])
+    write_registers(ctx, holding::OUTPUT, &[if value { 1 } else { 0 }]).await
+}
+
+pub async fn set_lcd_pattern(ctx: &mut Context, pattern: LcdPattern) -> Result<(), ()> {
+    write_block(
+        ctx,
+        &holding::LCD,
+        &[
+            (holding::LCD_PATTERN, pattern.code()),
+            (holding::LCD_DIGIT, pattern.digit()),
+        ],
+    )
+    .await
+}
+
+/// Writes the manufacturing data and saves it in non volatile memory
+pub async fn write_manufacturing_data(ctx: &mut Context, barcode: &Barcode) -> Result<(), ()> {
+    let mut values = vec![0; manufacturing::COUNT as usize];
+    for (field, text) in manufacturing::FIELDS.iter().zip(manufacturing_fields(barcode)) {
+        let encoded = field.encode(text).map_err(|()| {
+            logging::error(
+                "digiblock",
+                "Dato di produzione troppo lungo o non ASCII",
+                &[
+                    ("campo", &field.name),
+                    ("lunghezza", &text.len()),
+                    ("massimo", &(field.length * 2)),
+                ],
+            );
+        })?;
+        let start = (field.address - manufacturing::FIRST) as usize;
+        values[start..start + encoded.len()].copy_from_slice(&encoded);
+    }
+
+    ctx.write_multiple_registers(manufacturing::FIRST, &values)
         .await
-        .map_err(|_| ())
+        .map_err(|_| ())?;
+    write_registers(ctx, manufacturing::SAVE, &[1]).await
+}
+
+/// Reads back the manufacturing data, in the same order as the barcode fields
+pub async fn read_manufacturing_data(ctx: &mut Context) -> Result<Vec<String>, ()> {
+    let values = ctx
+        .read_holding_registers(manufacturing::FIRST, manufacturing::COUNT)
+        .await
+        .map_err(|_| ())?;
+
+    manufacturing::FIELDS
+        .iter()
+        .map(|field: &TextRegister| {
+            let start = (field.address - manufacturing::FIRST) as usize;
+            values
+                .get(start..start + field.length as usize)
+                .map(|v| field.decode(v))
+                .ok_or(())
+        })
+        .collect()
+}
+
+pub fn manufacturing_fields(barcode: &Barcode) -> [&str; 4] {
+    [
+        barcode.matricola.as_str(),
+        barcode.lotto_produzione.as_str(),
+        barcode.rev_hw.as_str(),
+        barcode.variante.as_str(),
+    ]
 }
 
 pub async fn get_short_circuit_adc(ctx: &mut Context) -> Result<bool, ()> {
@@ -0,0 +1,99 @@
+// Intel HEX parsing, producing the contiguous memory segments described by the file
+
+const RECORD_DATA: u8 = 0x00;
+const RECORD_END_OF_FILE: u8 = 0x01;
+const RECORD_EXTENDED_SEGMENT_ADDRESS: u8 = 0x02;
+const RECORD_START_SEGMENT_ADDRESS: u8 = 0x03;
+const RECORD_EXTENDED_LINEAR_ADDRESS: u8 = 0x04;
+const RECORD_START_LINEAR_ADDRESS: u8 = 0x05;
+
+#[derive(Clone, Debug, Default)]
+pub struct Segment {
+    pub address: u32,
+    pub data: Vec<u8>,
+}
+
+impl Segment {
+    fn end(&self) -> u32 {
+        self.address + self.data.len() as u32
+    }
+}
+
+/// Parses the content of an Intel HEX file, merging adjacent data records
+pub fn parse(content: &str) -> Result<Vec<Segment>, String> {
+    let mut segments: Vec<Segment> = Vec::new();
+    let mut base: u32 = 0;
+
+    for (number, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let bytes = parse_record(line).map_err(|e| format!("Riga {}: {}", number + 1, e))?;
+
+        let length = bytes[0] as usize;
+        let offset = u16::from_be_bytes([bytes[1], bytes[2]]) as u32;
+        let kind = bytes[3];
+        let data = &bytes[4..4 + length];
+
+        match kind {
+            RECORD_DATA => {
+                let address = base + offset;
+                match segments.last_mut() {
+                    Some(last) if last.end() == address => last.data.extend_from_slice(data),
+                    _ => segments.push(Segment {
+                        address,
+                        data: data.to_vec(),
+                    }),
+                }
+            }
+            RECORD_END_OF_FILE => return Ok(segments),
+            RECORD_EXTENDED_SEGMENT_ADDRESS if length == 2 => {
+                base = (u16::from_be_bytes([data[0], data[1]]) as u32) << 4;
+            }
+            RECORD_EXTENDED_LINEAR_ADDRESS if length == 2 => {
+                base = (u16::from_be_bytes([data[0], data[1]]) as u32) << 16;
+            }
+            // The entry point is not needed to program the flash
+            RECORD_START_SEGMENT_ADDRESS | RECORD_START_LINEAR_ADDRESS => (),
+            _ => {
+                return Err(format!(
+                    "Riga {}: record {:02X} non valido",
+                    number + 1,
+                    kind
+                ))
+            }
+        }
+    }
+
+    Err("Record di fine file mancante".into())
+}
+
+/// Decodes a single `:LLAAAATT[DD...]CC` record, checking length and checksum
+fn parse_record(line: &str) -> Result<Vec<u8>, String> {
+    let hex = line
+        .strip_prefix(':')
+        .ok_or_else(|| String::from("manca il carattere di inizio"))?;
+
+    if hex.len() % 2 != 0 || hex.len() < 10 {
+        return Err("lunghezza non valida".into());
+    }
+
+    let bytes = (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16))
+        .collect::<Result<Vec<u8>, _>>()
+        .map_err(|_| String::from("carattere non esadecimale"))?;
+
+    if bytes.len() != bytes[0] as usize + 5 {
+        return Err("lunghezza non valida".into());
+    }
+
+    let sum = bytes.iter().fold(0u8, |acc, b| acc.wrapping_add(*b));
+    if sum != 0 {
+        return Err("checksum errato".into());
+    }
+
+    Ok(bytes)
+}
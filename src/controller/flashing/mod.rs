@@ -0,0 +1,311 @@
+use iced_futures::futures::future::BoxFuture;
+use sha2::{Digest, Sha256};
+use std::{fs, path::PathBuf, process::Stdio, time::Duration};
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::process::Command;
+use tokio::sync::mpsc::UnboundedSender;
+use tokio::time::{sleep, timeout};
+
+use crate::controller::{digiblock, worker};
+use crate::logging;
+use crate::model::{
+    FirmwareImage, FirmwareManifest, FlasherKind, FlashingError, FlashingPhase, OptionBytes,
+};
+
+mod ihex;
+mod openocd;
+mod probers;
+mod stflash;
+
+const BINARIES_PATH: &str = "./binaries";
+const MANIFEST: &str = "./binaries/firmware.yaml";
+
+/// Time given to the production firmware to boot before connecting to it
+const PRODUCTION_BOOT_TIME: Duration = Duration::from_millis(2000);
+
+/// Loads the firmware manifest and checks that every image listed is present and intact
+pub fn load_manifest() -> Result<FirmwareManifest, String> {
+    let content =
+        fs::read_to_string(MANIFEST).map_err(|_| format!("Impossibile leggere {}", MANIFEST))?;
+    let mut manifest: FirmwareManifest = serde_yaml::from_str(content.as_str())
+        .map_err(|e| format!("Manifesto firmware non valido: {}", e))?;
+
+    if manifest.produzione.is_empty() {
+        return Err("Nessun firmware di produzione nel manifesto".into());
+    }
+
+    verify_image(&manifest.collaudo).map_err(|(_, e)| e)?;
+    for image in &mut manifest.produzione {
+        verify_image(image).map_err(|(_, e)| e)?;
+        if let Some(options) = &image.option_bytes {
+            options
+                .validate()
+                .map_err(|e| format!("Option byte di {}: {}", image.file, e))?;
+        }
+        if image.versione.is_empty() {
+            image.versione = version_from_file_name(image.file.as_str());
+        }
+    }
+
+    Ok(manifest)
+}
+
+/// Checks that the image is present, well formed and matches its checksum
+pub fn verify_image(image: &FirmwareImage) -> Result<(), (FlashingError, String)> {
+    let content = fs::read(image_path(image.file.as_str())).map_err(|_| {
+        let msg = format!("Firmware {} non trovato", image.file);
+        (FlashingError::InvalidImage, msg)
+    })?;
+
+    if !image.openocd.is_empty() && !image_path(image.openocd.as_str()).exists() {
+        return Err((
+            FlashingError::ProgrammerUnavailable,
+            format!("Configurazione openocd {} non trovata", image.openocd),
+        ));
+    }
+
+    if image.file.ends_with(".hex") {
+        let text = String::from_utf8_lossy(&content);
+        ihex::parse(&text).map_err(|e| {
+            let msg = format!("Firmware {} non valido: {}", image.file, e);
+            (FlashingError::InvalidImage, msg)
+        })?;
+    }
+
+    let checksum = Sha256::digest(&content)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect::<String>();
+
+    if checksum.eq_ignore_ascii_case(image.sha256.trim()) {
+        Ok(())
+    } else {
+        Err((
+            FlashingError::ChecksumMismatch,
+            format!("Checksum errato per il firmware {}", image.file),
+        ))
+    }
+}
+
+pub struct FlashingOutcome {
+    pub success: bool,
+    pub error: Option<FlashingError>,
+    /// Everything the programmer printed, stdout and stderr interleaved
+    pub output: Vec<String>,
+}
+
+impl FlashingOutcome {
+    fn succeeded(output: Vec<String>) -> Self {
+        Self {
+            success: true,
+            error: None,
+            output,
+        }
+    }
+
+    fn failed(error: FlashingError, output: Vec<String>) -> Self {
+        Self {
+            success: false,
+            error: Some(error),
+            output,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum FlashingEvent {
+    /// Current phase and fraction of it completed, from 0 to 1
+    Progress(FlashingPhase, f32),
+}
+
+pub trait Flasher: Send + Sync {
+    fn flash<'a>(
+        &'a self,
+        image: &'a FirmwareImage,
+        events: UnboundedSender<FlashingEvent>,
+    ) -> BoxFuture<'a, FlashingOutcome>;
+
+    /// Writes the option bytes and returns the option register read back afterwards
+    fn write_option_bytes<'a>(
+        &'a self,
+        _options: &'a OptionBytes,
+        _events: UnboundedSender<FlashingEvent>,
+    ) -> BoxFuture<'a, (FlashingOutcome, Option<u32>)> {
+        Box::pin(async move {
+            let msg = String::from("Il programmatore non supporta la scrittura degli option byte");
+            (
+                FlashingOutcome::failed(FlashingError::ProgrammerUnavailable, vec![msg]),
+                None,
+            )
+        })
+    }
+}
+
+pub fn flasher(kind: FlasherKind, chip: Option<String>) -> Box<dyn Flasher> {
+    match kind {
+        FlasherKind::Openocd => Box::new(openocd::Openocd),
+        FlasherKind::StFlash => Box::new(stflash::StFlash),
+        FlasherKind::ProbeRs => Box::new(probers::ProbeRs { chip }),
+    }
+}
+
+/// Programs `image` with `flasher`, forwarding its output and progress to `events`
+pub async fn load_firmware(
+    flasher: Box<dyn Flasher>,
+    image: FirmwareImage,
+    events: UnboundedSender<FlashingEvent>,
+) -> FlashingOutcome {
+    logging::info(
+        "flashing",
+        "Caricamento firmware",
+        &[("file", &image.file), ("versione", &image.versione)],
+    );
+
+    // The image could have been replaced since startup
+    if let Err((error, e)) = verify_image(&image) {
+        return FlashingOutcome::failed(error, vec![e]);
+    }
+
+    worker::reset().await;
+
+    flasher.flash(&image, events).await
+}
+
+/// Programs the option bytes with `flasher` and checks the value read back
+pub async fn write_option_bytes(
+    flasher: Box<dyn Flasher>,
+    options: OptionBytes,
+    events: UnboundedSender<FlashingEvent>,
+) -> (FlashingOutcome, Option<u32>) {
+    let (value, mask) = options.register();
+    logging::info(
+        "flashing",
+        "Scrittura option byte",
+        &[
+            ("valore", &format!("{:#010x}", value)),
+            ("maschera", &format!("{:#010x}", mask)),
+        ],
+    );
+
+    let (mut outcome, register) = flasher.write_option_bytes(&options, events.clone()).await;
+
+    if outcome.success && register.map(|r| r & mask) != Some(value & mask) {
+        let msg = format!(
+            "Option byte letti {} invece di {:#010x}",
+            register
+                .map(|r| format!("{:#010x}", r))
+                .unwrap_or("---".into()),
+            value
+        );
+        outcome.output.push(msg);
+        outcome.success = false;
+        outcome.error = Some(FlashingError::OptionBytesMismatch);
+    }
+
+    (outcome, register)
+}
+
+/// Finds the last hexadecimal value printed on a line containing `marker`
+fn parse_register(output: &[String], marker: &str) -> Option<u32> {
+    output
+        .iter()
+        .rev()
+        .filter(|line| line.to_lowercase().contains(marker))
+        .find_map(|line| {
+            line.split_whitespace()
+                .rev()
+                .find_map(|word| word.strip_prefix("0x"))
+                .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+        })
+}
+
+/// Runs an external programmer, collecting its output and forwarding each line to `events`.
+/// Lines containing one of `phases` mark the start of that phase
+async fn run_process(
+    command: &mut Command,
+    events: &UnboundedSender<FlashingEvent>,
+    phases: &[(&str, FlashingPhase)],
+) -> Result<(Option<i32>, Vec<String>), String> {
+    let mut child = command
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("Impossibile avviare il programmatore: {}", e))?;
+
+    let mut output = Vec::new();
+
+    if let (Some(stdout), Some(stderr)) = (child.stdout.take(), child.stderr.take()) {
+        let mut stdout = BufReader::new(stdout).lines();
+        let mut stderr = BufReader::new(stderr).lines();
+
+        loop {
+            let line = tokio::select! {
+                Ok(Some(line)) = stdout.next_line() => line,
+                Ok(Some(line)) = stderr.next_line() => line,
+                else => break,
+            };
+
+            let lowercase = line.to_lowercase();
+            if let Some((_, phase)) = phases.iter().find(|(s, _)| lowercase.contains(s)) {
+                events.send(FlashingEvent::Progress(*phase, 0.0)).ok();
+            }
+
+            output.push(line);
+        }
+    }
+
+    let code = child.wait().await.ok().and_then(|status| status.code());
+    Ok((code, output))
+}
+
+/// Turns the result of an external programmer into an outcome, looking for known failure
+/// signatures in its output
+fn process_outcome(
+    result: Result<(Option<i32>, Vec<String>), String>,
+    signatures: &[(&str, FlashingError)],
+) -> FlashingOutcome {
+    match result {
+        Ok((Some(0), output)) => FlashingOutcome::succeeded(output),
+        Ok((_, output)) => {
+            let error = parse_output(&output, signatures);
+            FlashingOutcome::failed(error, output)
+        }
+        Err(e) => FlashingOutcome::failed(FlashingError::ProgrammerUnavailable, vec![e]),
+    }
+}
+
+fn parse_output(output: &[String], signatures: &[(&str, FlashingError)]) -> FlashingError {
+    for line in output {
+        let line = line.to_lowercase();
+        for (signature, error) in signatures {
+            if line.contains(signature) {
+                return *error;
+            }
+        }
+    }
+
+    FlashingError::Unknown
+}
+
+/// Power cycles the board and reads back the version reported by the production firmware
+pub async fn verify_production_firmware(port: String) -> Option<String> {
+    logging::info("flashing", "Verifica firmware di produzione", &[]);
+
+    worker::reset().await;
+    sleep(PRODUCTION_BOOT_TIME).await;
+
+    let mut ctx = digiblock::connect(port.as_str()).await.ok()?;
+    let info = timeout(Duration::from_millis(200), digiblock::get_info(&mut ctx)).await;
+    ctx.disconnect().await.ok();
+
+    info.ok()?.ok().map(|info| info.firmware_version)
+}
+
+fn image_path(file: &str) -> PathBuf {
+    PathBuf::from(BINARIES_PATH).join(file)
+}
+
+fn version_from_file_name(file: &str) -> String {
+    file.replace("digiblock-production-", "")
+        .replace(".hex", "")
+}
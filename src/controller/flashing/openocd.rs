@@ -0,0 +1,105 @@
+use iced_futures::futures::future::BoxFuture;
+use tokio::process::Command;
+use tokio::sync::mpsc::UnboundedSender;
+
+use super::{
+    image_path, parse_register, process_outcome, run_process, Flasher, FlashingEvent,
+    FlashingOutcome,
+};
+use crate::model::{FirmwareImage, FlashingError, FlashingPhase, OptionBytes};
+
+/// Offset of `FLASH_OPTR` from the flash interface registers
+const FLASH_OPTR_OFFSET: u32 = 0x20;
+
+const PHASES: [(&str, FlashingPhase); 4] = [
+    ("target halted", FlashingPhase::Connect),
+    ("auto erase", FlashingPhase::Erase),
+    ("programming started", FlashingPhase::Program),
+    ("verify started", FlashingPhase::Verify),
+];
+
+const SIGNATURES: [(&str, FlashingError); 9] = [
+    (
+        "unable to connect to the target",
+        FlashingError::TargetNotFound,
+    ),
+    ("error connecting dp", FlashingError::TargetNotFound),
+    ("target not examined", FlashingError::TargetNotFound),
+    ("open failed", FlashingError::TargetNotFound),
+    ("read protect", FlashingError::ReadProtected),
+    ("device protected", FlashingError::ReadProtected),
+    ("failed erasing", FlashingError::EraseFailed),
+    ("erase error", FlashingError::EraseFailed),
+    ("verify failed", FlashingError::VerifyFailed),
+];
+
+/// Programs through an external openocd. The configuration file listed in the manifest only sets
+/// up the interface and the target, the verified image is passed explicitly
+pub struct Openocd;
+
+impl Flasher for Openocd {
+    fn flash<'a>(
+        &'a self,
+        image: &'a FirmwareImage,
+        events: UnboundedSender<FlashingEvent>,
+    ) -> BoxFuture<'a, FlashingOutcome> {
+        Box::pin(async move {
+            if image.openocd.is_empty() {
+                let msg = format!("Nessuna configurazione openocd per {}", image.file);
+                return FlashingOutcome::failed(FlashingError::ProgrammerUnavailable, vec![msg]);
+            }
+
+            // Braces quote the path for the Tcl interpreter of openocd
+            let program = format!(
+                "program {{{}}} verify reset exit",
+                image_path(image.file.as_str()).display()
+            );
+            let result = run_process(
+                Command::new("openocd")
+                    .arg("-f")
+                    .arg(image_path(image.openocd.as_str()))
+                    .args(&["-c", program.as_str()]),
+                &events,
+                &PHASES,
+            )
+            .await;
+
+            process_outcome(result, &SIGNATURES)
+        })
+    }
+
+    fn write_option_bytes<'a>(
+        &'a self,
+        options: &'a OptionBytes,
+        events: UnboundedSender<FlashingEvent>,
+    ) -> BoxFuture<'a, (FlashingOutcome, Option<u32>)> {
+        Box::pin(async move {
+            let (value, mask) = options.register();
+
+            // The STM32G0 is handled by the stm32l4x flash driver
+            let result = run_process(
+                Command::new("openocd")
+                    .arg("-f")
+                    .arg(image_path(options.openocd.as_str()))
+                    .args(&["-c", "init", "-c", "reset halt", "-c"])
+                    .arg(format!(
+                        "stm32l4x option_write 0 {:#x} {:#x} {:#x}",
+                        FLASH_OPTR_OFFSET, value, mask
+                    ))
+                    .arg("-c")
+                    .arg(format!("stm32l4x option_read 0 {:#x}", FLASH_OPTR_OFFSET))
+                    .args(&["-c", "stm32l4x option_load 0", "-c", "shutdown"]),
+                &events,
+                &[],
+            )
+            .await;
+
+            let register = result
+                .as_ref()
+                .ok()
+                .and_then(|(_, output)| parse_register(output, "option register"));
+
+            (process_outcome(result, &SIGNATURES), register)
+        })
+    }
+}
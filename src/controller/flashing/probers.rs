@@ -0,0 +1,130 @@
+use iced_futures::futures::future::BoxFuture;
+use tokio::sync::mpsc::UnboundedSender;
+
+use super::{Flasher, FlashingEvent, FlashingOutcome};
+#[cfg(feature = "probe-rs")]
+use crate::model::FlashingPhase;
+use crate::model::{FirmwareImage, FlashingError};
+
+#[cfg(feature = "probe-rs")]
+const SIGNATURES: [(&str, FlashingError); 5] = [
+    ("no probe", FlashingError::TargetNotFound),
+    ("attach", FlashingError::TargetNotFound),
+    ("protect", FlashingError::ReadProtected),
+    ("erase", FlashingError::EraseFailed),
+    ("verif", FlashingError::VerifyFailed),
+];
+
+/// Programs natively over SWD through probe-rs, without any external tool
+pub struct ProbeRs {
+    /// Target name as known by probe-rs, e.g. "STM32G030C8Tx"
+    pub chip: Option<String>,
+}
+
+impl Flasher for ProbeRs {
+    fn flash<'a>(
+        &'a self,
+        image: &'a FirmwareImage,
+        events: UnboundedSender<FlashingEvent>,
+    ) -> BoxFuture<'a, FlashingOutcome> {
+        Box::pin(async move {
+            let result = if let Some(chip) = self.chip.clone() {
+                load(chip, image.clone(), events.clone()).await
+            } else {
+                Err((
+                    FlashingError::ProgrammerUnavailable,
+                    String::from("Nessun chip configurato per probe-rs"),
+                ))
+            };
+
+            match result {
+                Ok(()) => FlashingOutcome::succeeded(vec![]),
+                Err((error, msg)) => FlashingOutcome::failed(error, vec![msg]),
+            }
+        })
+    }
+}
+
+#[cfg(feature = "probe-rs")]
+async fn load(
+    chip: String,
+    image: FirmwareImage,
+    events: UnboundedSender<FlashingEvent>,
+) -> Result<(), (FlashingError, String)> {
+    use probe_rs::flashing::{DownloadOptions, FlashProgress, ProgressEvent};
+    use probe_rs::{Permissions, Session};
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::Arc;
+
+    let content = std::fs::read_to_string(super::image_path(image.file.as_str()))
+        .map_err(|e| (FlashingError::ChecksumMismatch, e.to_string()))?;
+    let segments =
+        super::ihex::parse(content.as_str()).map_err(|e| (FlashingError::InvalidImage, e))?;
+    let total: u64 = segments.iter().map(|s| s.data.len() as u64).sum();
+
+    // probe-rs is blocking, keep it away from the runtime threads
+    tokio::task::spawn_blocking(move || {
+        let failed = |e: String| (super::parse_output(&[e.clone()], &SIGNATURES), e);
+
+        let mut session = Session::auto_attach(chip, Permissions::default())
+            .map_err(|e| failed(e.to_string()))?;
+
+        let mut loader = session.target().flash_loader();
+        for segment in &segments {
+            loader
+                .add_data(segment.address as u64, &segment.data)
+                .map_err(|e| failed(e.to_string()))?;
+        }
+
+        let programmed = Arc::new(AtomicU64::new(0));
+        let mut options = DownloadOptions::default();
+        options.verify = true;
+        options.progress = Some(FlashProgress::new(move |event| match event {
+            ProgressEvent::StartedErasing => {
+                events
+                    .send(FlashingEvent::Progress(FlashingPhase::Erase, 0.0))
+                    .ok();
+            }
+            ProgressEvent::StartedProgramming { .. } => {
+                events
+                    .send(FlashingEvent::Progress(FlashingPhase::Program, 0.0))
+                    .ok();
+            }
+            ProgressEvent::PageProgrammed { size, .. } => {
+                let done = programmed.fetch_add(size as u64, Ordering::Relaxed) + size as u64;
+                if total > 0 {
+                    events
+                        .send(FlashingEvent::Progress(
+                            FlashingPhase::Program,
+                            done as f32 / total as f32,
+                        ))
+                        .ok();
+                }
+            }
+            _ => (),
+        }));
+
+        loader
+            .commit(&mut session, options)
+            .map_err(|e| failed(e.to_string()))?;
+
+        session
+            .core(0)
+            .and_then(|mut core| core.reset())
+            .map_err(|e| failed(e.to_string()))
+    })
+    .await
+    .map_err(|e| (FlashingError::Unknown, e.to_string()))?
+}
+
+#[cfg(not(feature = "probe-rs"))]
+async fn load(
+    _chip: String,
+    _image: FirmwareImage,
+    _events: UnboundedSender<FlashingEvent>,
+) -> Result<(), (FlashingError, String)> {
+    Err((
+        FlashingError::ProgrammerUnavailable,
+        String::from("Tester compilato senza il supporto a probe-rs"),
+    ))
+}
@@ -0,0 +1,117 @@
+use iced_futures::futures::future::BoxFuture;
+use tokio::process::Command;
+use tokio::sync::mpsc::UnboundedSender;
+
+use super::{
+    image_path, parse_register, process_outcome, run_process, Flasher, FlashingEvent,
+    FlashingOutcome,
+};
+use crate::model::{FirmwareImage, FlashingError, FlashingPhase, OptionBytes};
+
+const PHASES: [(&str, FlashingPhase); 4] = [
+    ("chipid", FlashingPhase::Connect),
+    ("erased", FlashingPhase::Erase),
+    ("starting flash write", FlashingPhase::Program),
+    ("starting verification", FlashingPhase::Verify),
+];
+
+const SIGNATURES: [(&str, FlashingError); 5] = [
+    ("couldn't find any st-link", FlashingError::TargetNotFound),
+    ("unknown chip id", FlashingError::TargetNotFound),
+    ("read-out protection", FlashingError::ReadProtected),
+    ("failed to erase", FlashingError::EraseFailed),
+    ("verification of flash failed", FlashingError::VerifyFailed),
+];
+
+/// Programs through the stlink tools' `st-flash`
+pub struct StFlash;
+
+impl Flasher for StFlash {
+    fn flash<'a>(
+        &'a self,
+        image: &'a FirmwareImage,
+        events: UnboundedSender<FlashingEvent>,
+    ) -> BoxFuture<'a, FlashingOutcome> {
+        Box::pin(async move {
+            let result = run_process(
+                Command::new("st-flash")
+                    .args(&["--reset", "--format", "ihex", "write"])
+                    .arg(image_path(image.file.as_str())),
+                &events,
+                &PHASES,
+            )
+            .await;
+
+            process_outcome(result, &SIGNATURES)
+        })
+    }
+
+    fn write_option_bytes<'a>(
+        &'a self,
+        options: &'a OptionBytes,
+        events: UnboundedSender<FlashingEvent>,
+    ) -> BoxFuture<'a, (FlashingOutcome, Option<u32>)> {
+        Box::pin(async move {
+            let (value, mask) = options.register();
+
+            // st-flash writes the whole register, keep the bits not managed by the tester
+            let (current, mut output) = match read_option_bytes(&events).await {
+                Ok(read) => read,
+                Err(outcome) => return (outcome, None),
+            };
+
+            let result = run_process(
+                Command::new("st-flash")
+                    .args(&["--area=option", "write"])
+                    .arg(format!("{:#010x}", (current & !mask) | (value & mask))),
+                &events,
+                &[],
+            )
+            .await;
+
+            let mut outcome = process_outcome(result, &SIGNATURES);
+            output.append(&mut outcome.output);
+            outcome.output = output;
+
+            if !outcome.success {
+                return (outcome, None);
+            }
+
+            match read_option_bytes(&events).await {
+                Ok((register, mut output)) => {
+                    outcome.output.append(&mut output);
+                    (outcome, Some(register))
+                }
+                Err(mut failed) => {
+                    outcome.output.append(&mut failed.output);
+                    failed.output = outcome.output;
+                    (failed, None)
+                }
+            }
+        })
+    }
+}
+
+async fn read_option_bytes(
+    events: &UnboundedSender<FlashingEvent>,
+) -> Result<(u32, Vec<String>), FlashingOutcome> {
+    let result = run_process(
+        Command::new("st-flash").args(&["--area=option", "read"]),
+        events,
+        &[],
+    )
+    .await;
+
+    let outcome = process_outcome(result, &SIGNATURES);
+    if !outcome.success {
+        return Err(outcome);
+    }
+
+    match parse_register(&outcome.output, "0x") {
+        Some(register) => Ok((register, outcome.output)),
+        None => Err(FlashingOutcome::failed(
+            FlashingError::Unknown,
+            outcome.output,
+        )),
+    }
+}
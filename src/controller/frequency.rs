@@ -0,0 +1,183 @@
+use std::fs;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use rppal::{
+    gpio::{Gpio, Level},
+    pwm::{Channel, Polarity, Pwm},
+};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+use crate::logging;
+use crate::model::{Calibration, FrequencyGeneratorKind};
+
+const CALIBRATION: &str = "./calibration.yaml";
+
+/// Output wired to the frequency input of the board
+const OUTPUT_GPIO: u8 = 12;
+
+const PIGPIO_ADDRESS: &str = "127.0.0.1:8888";
+/// pigpio `hp` command: hardware PWM with frequency in Hz and duty cycle out of 1000000
+const PIGPIO_HARDWARE_PWM: u32 = 86;
+const PIGPIO_HALF_DUTY: u32 = 500_000;
+
+/// Frequency and measuring window used to calibrate the generator
+const CALIBRATION_FREQUENCY: f64 = 2000.0;
+const CALIBRATION_WINDOW: Duration = Duration::from_millis(1000);
+/// Corrections outside this range mean a wiring problem rather than an inaccurate clock
+const CALIBRATION_RANGE: (f64, f64) = (0.8, 1.2);
+
+static GENERATOR: Mutex<(FrequencyGeneratorKind, f64)> =
+    Mutex::new((FrequencyGeneratorKind::Pwm, 1.0));
+
+/// Selects the backend and loads the calibration of this fixture, `None` if it was never
+/// calibrated
+pub fn configure(kind: FrequencyGeneratorKind) -> Option<Calibration> {
+    let calibration: Option<Calibration> = fs::read_to_string(CALIBRATION)
+        .ok()
+        .and_then(|v| serde_yaml::from_str(v.as_str()).ok());
+    if calibration.is_none() {
+        logging::warning("frequency", "Attrezzatura non calibrata", &[]);
+    }
+
+    let correction = calibration
+        .as_ref()
+        .unwrap_or(&Calibration::default())
+        .correzione_frequenza;
+    *GENERATOR.lock().unwrap_or_else(|e| e.into_inner()) = (kind, correction);
+
+    calibration
+}
+
+fn generator() -> (FrequencyGeneratorKind, f64) {
+    *GENERATOR.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Starts generating `frequency` Hz on the output, with a 50% duty cycle
+pub async fn start(frequency: f64) -> Result<(), ()> {
+    let (kind, correction) = generator();
+    generate(kind, frequency * correction).await
+}
+
+pub async fn stop() -> Result<(), ()> {
+    let (kind, _) = generator();
+
+    if kind == FrequencyGeneratorKind::Pigpio {
+        pigpio_hardware_pwm(0, 0).await?;
+    }
+
+    // Takes the pin back from the PWM peripheral and leaves it low
+    let mut pin = Gpio::new()
+        .and_then(|gpio| gpio.get(OUTPUT_GPIO))
+        .map_err(|_| ())?
+        .into_output();
+    pin.set_low();
+    Ok(())
+}
+
+async fn generate(kind: FrequencyGeneratorKind, frequency: f64) -> Result<(), ()> {
+    match kind {
+        FrequencyGeneratorKind::Pwm => {
+            let mut pwm =
+                Pwm::with_frequency(Channel::Pwm0, frequency, 0.5, Polarity::Normal, true)
+                    .map_err(|_| ())?;
+            pwm.set_reset_on_drop(false);
+            Ok(())
+        }
+        FrequencyGeneratorKind::Pigpio => {
+            pigpio_hardware_pwm(frequency.round() as u32, PIGPIO_HALF_DUTY).await
+        }
+    }
+}
+
+/// Sends a `hp` command to the pigpio daemon over its socket interface
+async fn pigpio_hardware_pwm(frequency: u32, duty: u32) -> Result<(), ()> {
+    let mut stream = TcpStream::connect(PIGPIO_ADDRESS).await.map_err(|_| ())?;
+
+    // cmd, p1, p2, p3 (extension length) followed by the extension
+    let request: Vec<u8> = [PIGPIO_HARDWARE_PWM, OUTPUT_GPIO as u32, frequency, 4, duty]
+        .iter()
+        .flat_map(|word| word.to_le_bytes())
+        .collect();
+    stream.write_all(&request).await.map_err(|_| ())?;
+
+    let mut response = [0u8; 16];
+    stream.read_exact(&mut response).await.map_err(|_| ())?;
+
+    let result = i32::from_le_bytes([response[12], response[13], response[14], response[15]]);
+    if result < 0 {
+        logging::warning("frequency", "Errore di pigpio", &[("codice", &result)]);
+        Err(())
+    } else {
+        Ok(())
+    }
+}
+
+/// Counts the rising edges seen on `pin` over `window`
+pub async fn measure(pin: u8, window: Duration) -> Result<f64, String> {
+    tokio::task::spawn_blocking(move || count_frequency(pin, window))
+        .await
+        .map_err(|e| e.to_string())?
+}
+
+fn count_frequency(pin: u8, window: Duration) -> Result<f64, String> {
+    let pin = Gpio::new()
+        .and_then(|gpio| gpio.get(pin))
+        .map_err(|e| e.to_string())?
+        .into_input();
+
+    let start = Instant::now();
+    let mut last = pin.read();
+    let mut edges: u32 = 0;
+
+    while start.elapsed() < window {
+        let level = pin.read();
+        if level == Level::High && last == Level::Low {
+            edges += 1;
+        }
+        last = level;
+    }
+
+    Ok(edges as f64 / window.as_secs_f64())
+}
+
+/// Measures the uncorrected output on `loopback_pin` and stores the correction for this fixture
+pub async fn calibrate(loopback_pin: Option<u8>) -> Result<f64, String> {
+    let pin = loopback_pin.ok_or_else(|| String::from("Loopback PWM non configurato"))?;
+    let (kind, _) = generator();
+
+    generate(kind, CALIBRATION_FREQUENCY)
+        .await
+        .map_err(|_| String::from("Impossibile generare la frequenza"))?;
+    let measured = measure(pin, CALIBRATION_WINDOW).await;
+    stop().await.ok();
+
+    let measured = measured?;
+    if measured <= 0.0 {
+        return Err("Nessun segnale sul loopback".into());
+    }
+
+    let correction = CALIBRATION_FREQUENCY / measured;
+    if correction < CALIBRATION_RANGE.0 || correction > CALIBRATION_RANGE.1 {
+        return Err(format!(
+            "Misurati {:.0}Hz invece di {:.0}Hz",
+            measured, CALIBRATION_FREQUENCY
+        ));
+    }
+
+    let calibration = Calibration {
+        correzione_frequenza: correction,
+    };
+    let content = serde_yaml::to_string(&calibration).map_err(|e| e.to_string())?;
+    fs::write(CALIBRATION, content).map_err(|e| e.to_string())?;
+
+    GENERATOR.lock().unwrap_or_else(|e| e.into_inner()).1 = correction;
+    logging::info(
+        "frequency",
+        "Calibrazione salvata",
+        &[("correzione", &format!("{:.4}", correction))],
+    );
+
+    Ok(correction)
+}
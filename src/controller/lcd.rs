@@ -0,0 +1,174 @@
+// Camera check of the LCD segments against reference masks
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use tokio::process::Command;
+
+use crate::model::{LcdPattern, LcdSegment};
+
+/// One binary greyscale PGM per segment, white where the segment is
+const MASKS_PATH: &str = "./lcd";
+
+/// A segment is on when it is darker than this fraction of the rest of the display
+const SEGMENT_CONTRAST: f64 = 0.7;
+
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<u8>,
+}
+
+/// Parses a binary (P5) PGM image with at most 8 bits per pixel
+pub fn parse_pgm(content: &[u8]) -> Result<Image, String> {
+    let mut fields = Vec::new();
+    let mut position = 0;
+
+    // Magic number, width, height and maximum value, separated by whitespace and comments
+    while fields.len() < 4 {
+        while position < content.len() && content[position].is_ascii_whitespace() {
+            position += 1;
+        }
+        if position < content.len() && content[position] == b'#' {
+            while position < content.len() && content[position] != b'\n' {
+                position += 1;
+            }
+            continue;
+        }
+
+        let start = position;
+        while position < content.len() && !content[position].is_ascii_whitespace() {
+            position += 1;
+        }
+        if start == position {
+            return Err("intestazione incompleta".into());
+        }
+        fields.push(String::from_utf8_lossy(&content[start..position]).to_string());
+    }
+
+    if fields[0] != "P5" {
+        return Err("formato diverso da PGM binario".into());
+    }
+
+    let number = |field: &String| {
+        field
+            .parse::<usize>()
+            .map_err(|_| format!("valore {} non valido", field))
+    };
+    let width = number(&fields[1])?;
+    let height = number(&fields[2])?;
+    if number(&fields[3])? > 255 {
+        return Err("profondita' superiore a 8 bit".into());
+    }
+
+    // A single whitespace character separates the header from the pixels
+    let pixels = content
+        .get(position + 1..position + 1 + width * height)
+        .ok_or_else(|| String::from("immagine troncata"))?
+        .to_vec();
+
+    Ok(Image {
+        width,
+        height,
+        pixels,
+    })
+}
+
+/// Loads the reference masks, named after their file
+pub fn load_masks() -> Result<Vec<(String, Image)>, String> {
+    let mut files: Vec<PathBuf> = fs::read_dir(MASKS_PATH)
+        .map_err(|_| format!("Impossibile leggere {}", MASKS_PATH))?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| path.extension().map(|e| e == "pgm").unwrap_or(false))
+        .collect();
+    files.sort();
+
+    if files.is_empty() {
+        return Err(format!("Nessuna maschera in {}", MASKS_PATH));
+    }
+
+    files
+        .iter()
+        .map(|path| {
+            let name = path
+                .file_stem()
+                .map(|s| s.to_string_lossy().to_string())
+                .unwrap_or_default();
+            let content = fs::read(path).map_err(|_| format!("Impossibile leggere {}", name))?;
+            let mask = parse_pgm(&content).map_err(|e| format!("Maschera {}: {}", name, e))?;
+            Ok((name, mask))
+        })
+        .collect()
+}
+
+/// Takes a frame from the V4L2 device or reads the still image of `pattern` from the
+/// `source` directory, returning the PGM content
+pub async fn capture(source: &str, pattern: LcdPattern) -> Result<Vec<u8>, String> {
+    if source.starts_with("/dev/") {
+        let output = Command::new("ffmpeg")
+            .args(["-loglevel", "error", "-f", "v4l2", "-i", source])
+            .args(["-frames:v", "1", "-pix_fmt", "gray", "-vcodec", "pgm"])
+            .args(["-f", "image2pipe", "-"])
+            .output()
+            .await
+            .map_err(|e| format!("Impossibile avviare ffmpeg: {}", e))?;
+
+        if output.status.success() {
+            Ok(output.stdout)
+        } else {
+            Err(String::from_utf8_lossy(&output.stderr).trim().to_string())
+        }
+    } else {
+        let path = Path::new(source).join(format!("{}.pgm", pattern.name()));
+        fs::read(&path).map_err(|_| format!("Immagine {} non trovata", path.display()))
+    }
+}
+
+/// Tells whether the segment covered by `mask` is on in `image`
+fn segment_on(image: &Image, mask: &Image) -> bool {
+    let (mut inside, mut inside_count) = (0u64, 0u64);
+    let (mut outside, mut outside_count) = (0u64, 0u64);
+
+    for (pixel, mask) in image.pixels.iter().zip(mask.pixels.iter()) {
+        if *mask > 127 {
+            inside += *pixel as u64;
+            inside_count += 1;
+        } else {
+            outside += *pixel as u64;
+            outside_count += 1;
+        }
+    }
+
+    if inside_count == 0 || outside_count == 0 {
+        return false;
+    }
+
+    let inside = inside as f64 / inside_count as f64;
+    let outside = outside as f64 / outside_count as f64;
+    inside < outside * SEGMENT_CONTRAST
+}
+
+/// A segment passes if it is on with every segment lit and off with every segment unlit
+pub fn check(
+    masks: &[(String, Image)],
+    all_on: &Image,
+    all_off: &Image,
+) -> Result<Vec<LcdSegment>, String> {
+    for (name, mask) in masks {
+        for image in [all_on, all_off] {
+            if image.width != mask.width || image.height != mask.height {
+                return Err(format!(
+                    "Immagine {}x{} diversa dalla maschera {} ({}x{})",
+                    image.width, image.height, name, mask.width, mask.height
+                ));
+            }
+        }
+    }
+
+    Ok(masks
+        .iter()
+        .map(|(name, mask)| LcdSegment {
+            name: name.clone(),
+            passed: segment_on(all_on, mask) && !segment_on(all_off, mask),
+        })
+        .collect())
+}
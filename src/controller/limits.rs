@@ -0,0 +1,44 @@
+// Limits of the measured steps changed on this fixture from the maintenance screen
+use std::collections::BTreeMap;
+use std::fs;
+
+use crate::logging;
+use crate::model::{self, StepLimits};
+
+const LIMITS: &str = "./limits.yaml";
+
+/// Loads the limits changed on this fixture, the default ones apply to the other steps
+pub fn configure() {
+    let overrides: BTreeMap<String, StepLimits> = match fs::read_to_string(LIMITS) {
+        Ok(content) => serde_yaml::from_str(content.as_str()).unwrap_or_else(|e| {
+            logging::error(
+                "limits",
+                "Limiti non validi, uso quelli predefiniti",
+                &[("errore", &e)],
+            );
+            BTreeMap::new()
+        }),
+        Err(_) => BTreeMap::new(),
+    };
+
+    for (code, limits) in &overrides {
+        logging::info(
+            "limits",
+            "Limiti modificati",
+            &[
+                ("prova", code),
+                ("minimo", &limits.minimo),
+                ("massimo", &limits.massimo),
+            ],
+        );
+    }
+    model::override_limits(overrides);
+}
+
+/// Persists `overrides` and applies them from the next measure
+pub fn save(overrides: BTreeMap<String, StepLimits>) -> Result<(), String> {
+    let content = serde_yaml::to_string(&overrides).map_err(|e| e.to_string())?;
+    fs::write(LIMITS, content).map_err(|e| e.to_string())?;
+    model::override_limits(overrides);
+    Ok(())
+}
@@ -1,3 +1,4 @@
+mod audit;
 mod report;
 mod worker;
 
@@ -5,8 +6,15 @@ pub mod reles;
 pub mod adc;
 pub mod pwm;
 pub mod app;
+pub mod colorsensor;
 pub mod digiblock;
 pub mod flashing;
+pub mod frequency;
+pub mod lcd;
+pub mod limits;
+pub mod operators;
+pub mod registers;
+pub mod selftest;
 
 pub use self::report::save_report;
 
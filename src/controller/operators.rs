@@ -0,0 +1,145 @@
+// Registry of the operators and their authentication
+use std::fs::{self, File};
+use std::io::{ErrorKind, Read};
+
+use sha2::{Digest, Sha256};
+
+use crate::model::{Operator, OperatorRegistry, Role};
+
+const REGISTRY: &str = "./operators.yaml";
+/// Rounds of SHA-256 over the salted PIN, so that guessing it from the registry is slow
+const PIN_ROUNDS: u32 = 100_000;
+const SALT_LENGTH: usize = 16;
+
+/// Reads the registry, `None` if the fixture has none
+pub fn load() -> Result<Option<OperatorRegistry>, String> {
+    let content = match fs::read_to_string(REGISTRY) {
+        Ok(content) => content,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(format!("Registro operatori illeggibile: {}", e)),
+    };
+    let registry: OperatorRegistry = serde_yaml::from_str(content.as_str())
+        .map_err(|e| format!("Registro operatori non valido: {}", e))?;
+
+    if registry.operatori.is_empty() {
+        Err(String::from("Nessun operatore registrato"))
+    } else {
+        Ok(Some(registry))
+    }
+}
+
+/// Registry of the fixtures set up before `operators.yaml`: the single operator of
+/// `config.yaml`, with the lowest role, logging in with its code
+pub fn fallback(code: u8) -> OperatorRegistry {
+    OperatorRegistry {
+        operatori: vec![Operator {
+            codice: code,
+            nome: format!("Operatore {:02}", code),
+            ruolo: Role::Operatore,
+            badge: Some(code.to_string()),
+            pin: None,
+        }],
+        ..OperatorRegistry::default()
+    }
+}
+
+/// Finds the operator owning the scanned `badge` or, without a badge, the operator `code`
+/// matching the typed `pin`
+pub fn authenticate(
+    registry: &OperatorRegistry,
+    badge: &str,
+    code: &str,
+    pin: &str,
+) -> Option<Operator> {
+    let badge = badge.trim();
+    if !badge.is_empty() {
+        return registry
+            .operatori
+            .iter()
+            .find(|operator| operator.badge.as_deref().map(str::trim) == Some(badge))
+            .cloned();
+    }
+
+    let code: u8 = code.trim().parse().ok()?;
+    let pin = pin.trim();
+    if pin.is_empty() {
+        return None;
+    }
+
+    registry
+        .operatori
+        .iter()
+        .find(|operator| operator.codice == code)
+        .filter(|operator| {
+            operator
+                .pin
+                .as_deref()
+                .map(|record| verify_pin(record, pin))
+                .unwrap_or(false)
+        })
+        .cloned()
+}
+
+/// Record of `pin` for the registry, `<rounds>$<salt>$<hash>` with a random salt
+pub fn hash_pin(pin: &str) -> Result<String, String> {
+    let mut salt = [0u8; SALT_LENGTH];
+    File::open("/dev/urandom")
+        .and_then(|mut random| random.read_exact(&mut salt))
+        .map_err(|e| e.to_string())?;
+
+    Ok(format!(
+        "{}${}${}",
+        PIN_ROUNDS,
+        hex(&salt),
+        hex(&derive(pin.trim(), &salt, PIN_ROUNDS))
+    ))
+}
+
+fn verify_pin(record: &str, pin: &str) -> bool {
+    let mut parts = record.trim().split('$');
+    let (rounds, salt, hash) = match (parts.next(), parts.next(), parts.next()) {
+        (Some(rounds), Some(salt), Some(hash)) => (rounds, salt, hash),
+        _ => return false,
+    };
+    let (rounds, salt) = match (rounds.parse::<u32>(), unhex(salt)) {
+        (Ok(rounds), Some(salt)) if rounds > 0 => (rounds, salt),
+        _ => return false,
+    };
+
+    // Compared in full, so that the time taken does not tell how much of the hash matched
+    let expected = hex(&derive(pin, &salt, rounds));
+    expected.len() == hash.len()
+        && expected
+            .bytes()
+            .zip(hash.to_ascii_lowercase().bytes())
+            .fold(0, |difference, (a, b)| difference | (a ^ b))
+            == 0
+}
+
+fn derive(pin: &str, salt: &[u8], rounds: u32) -> Vec<u8> {
+    let mut digest = Sha256::new()
+        .chain_update(salt)
+        .chain_update(pin.as_bytes())
+        .finalize();
+    for _ in 1..rounds {
+        digest = Sha256::new()
+            .chain_update(salt)
+            .chain_update(digest)
+            .finalize();
+    }
+    digest.to_vec()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn unhex(text: &str) -> Option<Vec<u8>> {
+    if text.len() % 2 != 0 {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok())
+        .collect()
+}
@@ -1,10 +1,16 @@
-use std::{error::Error, time::Duration};
+use std::{
+    error::Error,
+    thread,
+    time::{Duration, Instant},
+};
 
 use rppal::{
     gpio::Gpio,
     pwm::{Channel, Polarity, Pwm},
 };
 
+use crate::logging;
+
 pub fn set_420ma(milliamperes: i32) -> Result<(), Box<dyn Error>> {
     // Enable PWM channel 0 (BCM GPIO 18, physical pin 12) at 2 Hz with a 25% duty cycle.
 
@@ -17,45 +23,132 @@ pub fn set_420ma(milliamperes: i32) -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
-pub async fn set_frequency(frequency: u16) -> Result<(), ()> {
-    /*let mut pin = Gpio::new().unwrap().get(12).unwrap().into_output();
-    pin.set_reset_on_drop(false);
-    let period = 1000_000 / frequency as u64;
-    pin.set_pwm(Duration::from_micros(period), Duration::from_micros(period/2)).unwrap();
-    Ok(())*/
+/// Output wired to the pulse input of the board
+const PULSE_GPIO: u8 = 12;
+/// Core the pulse thread is pinned to, the last one of the Raspberry, and its real-time priority
+const PULSE_CPU: usize = 3;
+const PULSE_PRIORITY: i32 = 80;
 
-    // Enable PWM channel 0 (BCM GPIO 18, physical pin 12) at 2 Hz with a 25% duty cycle.
-    let frequency = (frequency as f64) * 0.932; // * 0.95;
+/// What `pulse_train` actually put on the output
+#[derive(Clone, Copy, Debug)]
+pub struct PulseReport {
+    /// Pulses emitted
+    pub count: u16,
+    /// Pulses with a high or low phase shorter than half of the requested one
+    pub short_pulses: u16,
+    pub duration: Duration,
+    /// Largest difference between a period and the requested one
+    pub max_period_error: Duration,
+}
+
+impl PulseReport {
+    /// True if every period was within `tolerance` of `period` and no phase was short
+    pub fn regular(&self, period: Duration, tolerance: f64) -> bool {
+        self.short_pulses == 0
+            && self.max_period_error.as_secs_f64() <= period.as_secs_f64() * tolerance
+    }
+}
 
-    let mut pwm = Pwm::with_frequency(Channel::Pwm0, frequency, 0.5, Polarity::Normal, true)
+/// Emits `count` pulses with the given `period`, timed by busy waiting on a real-time thread
+/// of its own since the tokio timers have a resolution of about a millisecond
+pub async fn pulse_train(count: u16, period: Duration) -> Result<PulseReport, ()> {
+    let (sender, receiver) = tokio::sync::oneshot::channel();
+
+    thread::Builder::new()
+        .name("pulse-train".into())
+        .spawn(move || {
+            if let Err(e) = make_realtime() {
+                logging::warning(
+                    "pwm",
+                    "Thread degli impulsi senza priorita' real-time",
+                    &[("errore", &e)],
+                );
+            }
+            sender.send(emit_pulses(count, period)).ok();
+        })
         .map_err(|_| ())?;
-    pwm.set_reset_on_drop(false);
-    Ok(())
 
-    // Need to use pigs, for some reason rppal is very inaccurate
-
-    /*
-    use tokio::process::Command;
-         Command::new("pigs")
-        .args(&["hp", "12", format!("{}", frequency).as_str(), "500000"])
-        .status()
-        .await
-        .map(|_| ())
-        .map_err(|_| ())
-        */
+    receiver.await.map_err(|_| ())?
 }
 
-pub async fn toggle_times(times: u16) -> Result<(), Box<dyn Error>> {
-    let mut pin = Gpio::new()?.get(12)?.into_output();
+/// Pins the current thread to `PULSE_CPU` and schedules it as SCHED_FIFO, so that neither the
+/// other threads nor a migration interrupt the busy waiting
+fn make_realtime() -> Result<(), std::io::Error> {
+    // SAFETY: plain system calls on the current thread, with structures initialised here
+    unsafe {
+        let mut cpus: libc::cpu_set_t = std::mem::zeroed();
+        libc::CPU_SET(PULSE_CPU, &mut cpus);
+        if libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &cpus) != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
 
-    for _ in 0..times {
-        pin.set_low();
-        tokio::time::sleep(Duration::from_micros(50)).await;
-        pin.set_high();
-        tokio::time::sleep(Duration::from_micros(50)).await;
+        let param = libc::sched_param {
+            sched_priority: PULSE_PRIORITY,
+        };
+        if libc::sched_setscheduler(0, libc::SCHED_FIFO, &param) != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
     }
+    Ok(())
+}
 
+fn emit_pulses(count: u16, period: Duration) -> Result<PulseReport, ()> {
+    let mut pin = Gpio::new()
+        .and_then(|gpio| gpio.get(PULSE_GPIO))
+        .map_err(|_| ())?
+        .into_output();
     pin.set_low();
 
-    Ok(())
+    let half = period / 2;
+    // A late wake up shortens the following phase, below this the board may not see the pulse
+    let shortest = half / 2;
+    let start = Instant::now();
+    let mut deadline = start;
+    let mut last_fall = start;
+    let mut last_rise: Option<Instant> = None;
+    let mut max_period_error = Duration::ZERO;
+    let mut emitted: u16 = 0;
+    let mut short_pulses: u16 = 0;
+
+    for _ in 0..count {
+        deadline += half;
+        wait_until(deadline);
+        pin.set_high();
+        let rise = Instant::now();
+        let low = rise - last_fall;
+
+        if let Some(last) = last_rise {
+            let elapsed = rise - last;
+            let error = if elapsed > period {
+                elapsed - period
+            } else {
+                period - elapsed
+            };
+            max_period_error = max_period_error.max(error);
+        }
+        last_rise = Some(rise);
+
+        deadline += half;
+        wait_until(deadline);
+        pin.set_low();
+        last_fall = Instant::now();
+
+        emitted += 1;
+        if low < shortest || last_fall - rise < shortest {
+            short_pulses += 1;
+        }
+    }
+
+    Ok(PulseReport {
+        count: emitted,
+        short_pulses,
+        duration: start.elapsed(),
+        max_period_error,
+    })
+}
+
+fn wait_until(deadline: Instant) {
+    while Instant::now() < deadline {
+        std::hint::spin_loop();
+    }
 }
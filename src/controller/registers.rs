@@ -0,0 +1,247 @@
+// Modbus register map of the Digiblock test firmware.
+//
+// Every register exposed by the test firmware is described here once; both the tester and any
+// simulated slave should refer to these definitions instead of raw addresses.
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Register {
+    pub name: &'static str,
+    pub address: u16,
+    /// Multiplier converting the raw register value into `unit`
+    pub scale: f64,
+    pub unit: &'static str,
+}
+
+impl Register {
+    pub const fn new(name: &'static str, address: u16, scale: f64, unit: &'static str) -> Self {
+        Self {
+            name,
+            address,
+            scale,
+            unit,
+        }
+    }
+
+    pub fn scaled(&self, raw: u16) -> f64 {
+        (raw as f64) * self.scale
+    }
+
+    pub fn raw(&self, value: f64) -> u16 {
+        (value / self.scale).round() as u16
+    }
+}
+
+/// ASCII text spanning `length` consecutive registers, two characters each, most significant
+/// byte first and padded with zeros
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TextRegister {
+    pub name: &'static str,
+    pub address: u16,
+    pub length: u16,
+}
+
+impl TextRegister {
+    pub const fn new(name: &'static str, address: u16, length: u16) -> Self {
+        Self {
+            name,
+            address,
+            length,
+        }
+    }
+
+    pub fn encode(&self, text: &str) -> Result<Vec<u16>, ()> {
+        let bytes = text.as_bytes();
+        if !text.is_ascii() || bytes.len() > (self.length as usize) * 2 {
+            return Err(());
+        }
+
+        Ok((0..self.length as usize)
+            .map(|i| {
+                let high = bytes.get(i * 2).copied().unwrap_or(0) as u16;
+                let low = bytes.get(i * 2 + 1).copied().unwrap_or(0) as u16;
+                (high << 8) | low
+            })
+            .collect())
+    }
+
+    pub fn decode(&self, values: &[u16]) -> String {
+        values
+            .iter()
+            .flat_map(|v| v.to_be_bytes())
+            .take_while(|b| *b != 0)
+            .map(|b| b as char)
+            .collect()
+    }
+}
+
+/// A contiguous block of registers, read or written with a single request
+pub struct RegisterBlock<const N: usize> {
+    pub registers: [Register; N],
+}
+
+impl<const N: usize> RegisterBlock<N> {
+    pub fn first(&self) -> u16 {
+        self.registers.iter().map(|r| r.address).min().unwrap_or(0)
+    }
+
+    pub fn count(&self) -> u16 {
+        self.registers
+            .iter()
+            .map(|r| r.address + 1)
+            .max()
+            .unwrap_or(0)
+            - self.first()
+    }
+
+    /// Raw values to write starting at `first()`, zero for the registers missing from `values`
+    pub fn encode(&self, values: &[(Register, u16)]) -> Vec<u16> {
+        let mut raw = vec![0; self.count() as usize];
+        for (register, value) in values {
+            let slot = register
+                .address
+                .checked_sub(self.first())
+                .and_then(|i| raw.get_mut(i as usize));
+            if let Some(slot) = slot {
+                *slot = *value;
+            }
+        }
+        raw
+    }
+
+    /// Returns the raw value of `register` from a response read starting at `first()`
+    pub fn get(&self, values: &[u16], register: Register) -> Option<u16> {
+        values
+            .get(register.address.checked_sub(self.first())? as usize)
+            .copied()
+    }
+}
+
+pub mod input {
+    use super::{Register, RegisterBlock};
+
+    /// Bit 0: left button, bit 1: right button
+    pub const BUTTONS: Register = Register::new("pulsanti", 0, 1.0, "");
+    pub const PERIOD: Register = Register::new("periodo", 1, 1.0, "us");
+    pub const PULSES: Register = Register::new("impulsi", 2, 1.0, "");
+    pub const MA420: Register = Register::new("4-20mA", 3, 0.01, "mA");
+    pub const SHORT_CIRCUIT_ADC: Register = Register::new("cortocircuito analogico", 4, 1.0, "");
+    /// Unused by the tester, kept so the block stays contiguous
+    pub const RESERVED: Register = Register::new("riservato", 5, 1.0, "");
+    pub const SHORT_CIRCUIT_OUT: Register = Register::new("cortocircuito uscita", 6, 1.0, "");
+
+    pub const STATE: RegisterBlock<7> = RegisterBlock {
+        registers: [
+            BUTTONS,
+            PERIOD,
+            PULSES,
+            MA420,
+            SHORT_CIRCUIT_ADC,
+            RESERVED,
+            SHORT_CIRCUIT_OUT,
+        ],
+    };
+
+    pub const FIRMWARE_VERSION_MAJOR: Register =
+        Register::new("versione firmware (major)", 7, 1.0, "");
+    pub const FIRMWARE_VERSION_MINOR: Register =
+        Register::new("versione firmware (minor)", 8, 1.0, "");
+    pub const FIRMWARE_VERSION_PATCH: Register =
+        Register::new("versione firmware (patch)", 9, 1.0, "");
+    pub const HARDWARE_REVISION: Register = Register::new("revisione hardware", 10, 1.0, "");
+    /// 96 bit unique ID of the MCU, most significant word first
+    pub const UID_0: Register = Register::new("UID 0", 11, 1.0, "");
+    pub const UID_1: Register = Register::new("UID 1", 12, 1.0, "");
+    pub const UID_2: Register = Register::new("UID 2", 13, 1.0, "");
+    pub const UID_3: Register = Register::new("UID 3", 14, 1.0, "");
+    pub const UID_4: Register = Register::new("UID 4", 15, 1.0, "");
+    pub const UID_5: Register = Register::new("UID 5", 16, 1.0, "");
+
+    pub const IDENTITY: RegisterBlock<10> = RegisterBlock {
+        registers: [
+            FIRMWARE_VERSION_MAJOR,
+            FIRMWARE_VERSION_MINOR,
+            FIRMWARE_VERSION_PATCH,
+            HARDWARE_REVISION,
+            UID_0,
+            UID_1,
+            UID_2,
+            UID_3,
+            UID_4,
+            UID_5,
+        ],
+    };
+}
+
+pub mod holding {
+    use super::{Register, RegisterBlock};
+
+    pub const MODE: Register = Register::new("modalita'", 0, 1.0, "");
+    pub const RESET_PULSES: Register = Register::new("azzeramento impulsi", 1, 1.0, "");
+    pub const OUTPUT: Register = Register::new("uscita", 2, 1.0, "");
+    pub const BACKLIGHT: Register = Register::new("intensita' retroilluminazione", 3, 1.0, "%");
+    /// One of the `RGB_*` values
+    pub const RGB: Register = Register::new("colore retroilluminazione", 4, 1.0, "");
+    /// Pattern shown on the LCD by the test firmware, see `LcdPattern::code`
+    pub const LCD_PATTERN: Register = Register::new("pattern LCD", 5, 1.0, "");
+    /// Digit shown on every position when `LCD_PATTERN` selects the digit pattern
+    pub const LCD_DIGIT: Register = Register::new("cifra LCD", 6, 1.0, "");
+
+    /// Pattern and digit, written together
+    pub const LCD: RegisterBlock<2> = RegisterBlock {
+        registers: [LCD_PATTERN, LCD_DIGIT],
+    };
+
+    /// Intensity and colour, written together
+    pub const LIGHT: RegisterBlock<2> = RegisterBlock {
+        registers: [BACKLIGHT, RGB],
+    };
+
+    pub const DIGITAL_MODE: u16 = 1;
+    pub const ANALOG_MODE: u16 = 2;
+
+    pub const RGB_WHITE: u16 = 6;
+    pub const RGB_RED: u16 = 7;
+    pub const RGB_GREEN: u16 = 2;
+    pub const RGB_BLUE: u16 = 1;
+
+    /// Manufacturing data, stored in non volatile memory by the test firmware
+    pub mod manufacturing {
+        use super::super::{Register, TextRegister};
+
+        pub const MATRICOLA: TextRegister = TextRegister::new("matricola", 16, 8);
+        pub const LOTTO_PRODUZIONE: TextRegister = TextRegister::new("lotto produzione", 24, 8);
+        pub const REV_HW: TextRegister = TextRegister::new("revisione hardware", 32, 2);
+        pub const VARIANTE: TextRegister = TextRegister::new("variante", 34, 2);
+        /// Writing 1 saves the manufacturing data in non volatile memory
+        pub const SAVE: Register = Register::new("salvataggio dati di produzione", 36, 1.0, "");
+
+        pub const FIELDS: [TextRegister; 4] = [MATRICOLA, LOTTO_PRODUZIONE, REV_HW, VARIANTE];
+        /// Registers spanned by `FIELDS`, read and written with a single request
+        pub const FIRST: u16 = first();
+        pub const COUNT: u16 = end() - FIRST;
+
+        const fn first() -> u16 {
+            let mut first = u16::MAX;
+            let mut i = 0;
+            while i < FIELDS.len() {
+                if FIELDS[i].address < first {
+                    first = FIELDS[i].address;
+                }
+                i += 1;
+            }
+            first
+        }
+
+        const fn end() -> u16 {
+            let mut end = 0;
+            let mut i = 0;
+            while i < FIELDS.len() {
+                if FIELDS[i].address + FIELDS[i].length > end {
+                    end = FIELDS[i].address + FIELDS[i].length;
+                }
+                i += 1;
+            }
+            end
+        }
+    }
+}
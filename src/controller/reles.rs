@@ -1,11 +1,16 @@
-use std::error::Error;
+use std::collections::HashMap;
+use std::fs;
+use std::process::Command;
+use std::sync::{Mutex, MutexGuard, TryLockError};
 use std::thread;
 use std::time::Duration;
 
-use rppal::gpio::Gpio;
-use rppal::system::DeviceInfo;
+use rppal::gpio::{Gpio, OutputPin};
 
-#[derive(Clone, Copy, Debug)]
+use crate::logging;
+use crate::model::MaintenanceCounters;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub enum Rele {
     ShortCircuitOutput,
     ShortCircuitAnalog,
@@ -14,10 +19,63 @@ pub enum Rele {
     UsbGround,
     DigitalMode,
     AnalogMode,
+    /// Solenoids pressing the buttons of the board, when fitted
+    LeftButtonActuator,
+    RightButtonActuator,
 }
 
-pub fn update(rele: Rele, value: bool) -> Result<(), ()> {
-    let gpio = match rele {
+pub const RELES: [Rele; 9] = [
+    Rele::ShortCircuitOutput,
+    Rele::ShortCircuitAnalog,
+    Rele::CorrectPower,
+    Rele::IncorrectPower,
+    Rele::UsbGround,
+    Rele::DigitalMode,
+    Rele::AnalogMode,
+    Rele::LeftButtonActuator,
+    Rele::RightButtonActuator,
+];
+
+/// Pairs of relays that must never be closed together
+const EXCLUSIONS: [(Rele, Rele); 2] = [
+    (Rele::CorrectPower, Rele::IncorrectPower),
+    (Rele::AnalogMode, Rele::DigitalMode),
+];
+
+/// Time given to a relay to open before its exclusive counterpart is closed
+const BREAK_BEFORE_MAKE: Duration = Duration::from_millis(20);
+
+const COUNTERS: &str = "./maintenance.yaml";
+
+static CONTROLLER: Mutex<Option<ReleController>> = Mutex::new(None);
+/// Whether the button actuators are fitted, set before the pins are claimed
+static ACTUATORS: Mutex<bool> = Mutex::new(false);
+
+/// Declares whether the fixture has the button actuators, their pins are left alone otherwise
+pub fn configure(actuators: bool) {
+    *ACTUATORS.lock().unwrap_or_else(|e| e.into_inner()) = actuators;
+
+    // The relays were already opened at startup, the pins are claimed again on the next use
+    let mut guard = controller();
+    if let Some(c) = guard.as_mut() {
+        c.all_off();
+    }
+    *guard = None;
+}
+
+/// Relays present on this fixture
+pub fn fitted() -> Vec<Rele> {
+    let actuators = *ACTUATORS.lock().unwrap_or_else(|e| e.into_inner());
+    RELES
+        .into_iter()
+        .filter(|rele| {
+            actuators || !matches!(rele, Rele::LeftButtonActuator | Rele::RightButtonActuator)
+        })
+        .collect()
+}
+
+pub fn gpio(rele: Rele) -> u8 {
+    match rele {
         Rele::ShortCircuitOutput => 14,
         Rele::ShortCircuitAnalog => 2,
         Rele::AnalogMode => 3, // 420ma
@@ -25,95 +83,226 @@ pub fn update(rele: Rele, value: bool) -> Result<(), ()> {
         Rele::IncorrectPower => 17,
         Rele::UsbGround => 25,
         Rele::DigitalMode => 22, // Frequency
-    };
+        Rele::LeftButtonActuator => 23,
+        Rele::RightButtonActuator => 24,
+    }
+}
 
-    if value {
-        match rele {
-            Rele::CorrectPower => {
-                let mut pin = Gpio::new()
-                    .map_err(|_| ())?
-                    .get(17)
-                    .map_err(|_| ())?
-                    .into_output();
-                pin.set_reset_on_drop(false);
-                pin.set_low();
-            }
-            Rele::IncorrectPower => {
-                let mut pin = Gpio::new()
-                    .map_err(|_| ())?
-                    .get(4)
-                    .map_err(|_| ())?
-                    .into_output();
-                pin.set_reset_on_drop(false);
+fn exclusive(rele: Rele) -> Option<Rele> {
+    EXCLUSIONS.iter().find_map(|&(a, b)| {
+        if a == rele {
+            Some(b)
+        } else if b == rele {
+            Some(a)
+        } else {
+            None
+        }
+    })
+}
+
+/// Owns the relay pins for the whole life of the application and remembers their state
+struct ReleController {
+    pins: HashMap<Rele, OutputPin>,
+    states: HashMap<Rele, bool>,
+    counters: MaintenanceCounters,
+}
+
+impl ReleController {
+    fn new() -> Result<Self, ()> {
+        let chip = Gpio::new().map_err(|_| ())?;
+        let mut pins = HashMap::new();
+
+        let fitted = fitted();
+        for &rele in &fitted {
+            let mut pin = chip.get(gpio(rele)).map_err(|_| ())?.into_output();
+            pin.set_reset_on_drop(false);
+            pins.insert(rele, pin);
+        }
+
+        let mut counters: MaintenanceCounters = fs::read_to_string(COUNTERS)
+            .ok()
+            .and_then(|v| serde_yaml::from_str(v.as_str()).ok())
+            .unwrap_or_default();
+        for rele in fitted {
+            counters.rele.entry(format!("{:?}", rele)).or_default();
+        }
+
+        Ok(Self {
+            pins,
+            states: HashMap::new(),
+            counters,
+        })
+    }
+
+    /// `count` is false when the switching is not wear from testing boards, as in the self-test
+    fn write(&mut self, rele: Rele, value: bool, count: bool) {
+        if let Some(pin) = self.pins.get_mut(&rele) {
+            if value {
+                pin.set_high();
+            } else {
                 pin.set_low();
             }
-            Rele::DigitalMode => {
-                let mut pin = Gpio::new()
-                    .map_err(|_| ())?
-                    .get(3)
-                    .map_err(|_| ())?
-                    .into_output();
-                pin.set_reset_on_drop(false);
-                pin.set_low();
+
+            if self.states.insert(rele, value) != Some(true) && value && count {
+                *self.counters.rele.entry(format!("{:?}", rele)).or_default() += 1;
             }
-            Rele::AnalogMode => {
-                let mut pin = Gpio::new()
-                    .map_err(|_| ())?
-                    .get(22)
-                    .map_err(|_| ())?
-                    .into_output();
-                pin.set_reset_on_drop(false);
-                pin.set_low();
+        }
+    }
+
+    fn update(&mut self, rele: Rele, value: bool, count: bool) {
+        if value {
+            if let Some(other) = exclusive(rele) {
+                if self.state(other) {
+                    logging::debug(
+                        "reles",
+                        "Apertura prima della chiusura",
+                        &[
+                            ("aperto", &format!("{:?}", other)),
+                            ("chiuso", &format!("{:?}", rele)),
+                        ],
+                    );
+                    self.write(other, false, count);
+                    thread::sleep(BREAK_BEFORE_MAKE);
+                } else {
+                    // Force it open anyway, its state is unknown until first written
+                    self.write(other, false, count);
+                }
             }
-            _ => (),
         }
+
+        logging::debug(
+            "reles",
+            "Commutazione",
+            &[
+                ("rele", &format!("{:?}", rele)),
+                ("gpio", &gpio(rele)),
+                ("stato", &value),
+            ],
+        );
+        self.write(rele, value, count);
+    }
+
+    fn state(&self, rele: Rele) -> bool {
+        self.states.get(&rele).copied().unwrap_or(false)
     }
 
-    let mut pin = Gpio::new()
-        .map_err(|_| ())?
-        .get(gpio)
-        .map_err(|_| ())?
-        .into_output();
-    pin.set_reset_on_drop(false);
+    fn all_off(&mut self) {
+        for rele in RELES {
+            self.write(rele, false, false);
+        }
+    }
+}
 
-    println!("Setting pin {:?} to {}", rele, value);
+fn controller() -> MutexGuard<'static, Option<ReleController>> {
+    // A panic while driving a relay must not prevent turning everything off later
+    CONTROLLER.lock().unwrap_or_else(|e| e.into_inner())
+}
 
-    if value {
-        pin.set_high();
-    } else {
-        pin.set_low();
+fn with_controller<T>(f: impl FnOnce(&mut ReleController) -> T) -> Result<T, ()> {
+    let mut guard = controller();
+    if guard.is_none() {
+        *guard = Some(ReleController::new()?);
     }
+    guard.as_mut().map(f).ok_or(())
+}
 
-    Ok(())
+pub fn update(rele: Rele, value: bool) -> Result<(), ()> {
+    with_controller(|c| c.update(rele, value, true))
+}
+
+/// Like `update`, without adding to the maintenance counters
+pub fn update_uncounted(rele: Rele, value: bool) -> Result<(), ()> {
+    with_controller(|c| c.update(rele, value, false))
+}
+
+/// Counts a board inserted on the fixture pogo pins
+pub fn record_insertion() {
+    with_controller(|c| c.counters.inserimenti += 1).ok();
+    save_counters();
+}
+
+pub fn counters() -> MaintenanceCounters {
+    with_controller(|c| c.counters.clone()).unwrap_or_default()
+}
+
+/// Zeroes the count of the relay named `rele`, or the insertions if `None`, after a part
+/// was replaced
+pub fn reset_counter(rele: Option<&str>) {
+    with_controller(|c| match rele {
+        Some(rele) => {
+            c.counters.rele.insert(rele.into(), 0);
+        }
+        None => c.counters.inserimenti = 0,
+    })
+    .ok();
+    save_counters();
+}
+
+pub fn save_counters() {
+    if let Ok(counters) = with_controller(|c| c.counters.clone()) {
+        if let Ok(content) = serde_yaml::to_string(&counters) {
+            fs::write(COUNTERS, content).ok();
+        }
+    }
 }
 
 pub fn all_off() {
-    update(Rele::AnalogMode, false).ok();
-    update(Rele::ShortCircuitOutput, false).ok();
-    update(Rele::ShortCircuitAnalog, false).ok();
-    update(Rele::CorrectPower, false).ok();
-    update(Rele::IncorrectPower, false).ok();
-    update(Rele::UsbGround, false).ok();
-    update(Rele::DigitalMode, false).ok();
-}
-
-pub fn _set_reles() -> Result<(), Box<dyn Error>> {
-    println!("Blinking an LED on a {}.", DeviceInfo::new()?.model());
-
-    for x in [25, 2, 3, 4, 17, 27, 22, 14] {
-        println!("Working with {}", x);
-        let mut pin = Gpio::new()?.get(x)?.into_output();
-
-        // Blink the LED by setting the pin's logic level high for 500 ms.
-        pin.set_high();
-        thread::sleep(Duration::from_millis(500));
-        pin.set_low();
-        thread::sleep(Duration::from_millis(500));
-        pin.set_high();
-        thread::sleep(Duration::from_millis(500));
-        pin.set_low();
-        thread::sleep(Duration::from_millis(2000));
+    with_controller(|c| c.all_off()).ok();
+}
+
+/// Opens every relay from a panic, without waiting for a lock that the panicking thread may hold
+fn emergency_off() {
+    let mut guard = match CONTROLLER.try_lock() {
+        Ok(guard) => guard,
+        Err(TryLockError::Poisoned(e)) => e.into_inner(),
+        Err(TryLockError::WouldBlock) => {
+            force_off().ok();
+            return;
+        }
+    };
+    match guard.as_mut() {
+        Some(c) => c.all_off(),
+        None => {
+            force_off().ok();
+        }
     }
+}
 
+/// Drives every relay pin low through new handles, bypassing the controller
+fn force_off() -> Result<(), rppal::gpio::Error> {
+    let chip = Gpio::new()?;
+    for rele in RELES {
+        match chip.get(gpio(rele)) {
+            Ok(pin) => {
+                let mut pin = pin.into_output();
+                pin.set_reset_on_drop(false);
+                pin.set_low();
+            }
+            // rppal hands out each pin once per process, while the controller is alive its pins
+            // can only be driven from outside
+            Err(_) => set_low_external(gpio(rele)),
+        }
+    }
     Ok(())
 }
+
+fn set_low_external(pin: u8) {
+    let pin = pin.to_string();
+    for tool in ["pinctrl", "raspi-gpio"] {
+        let status = Command::new(tool)
+            .args(["set", pin.as_str(), "op", "dl"])
+            .status();
+        if matches!(status, Ok(status) if status.success()) {
+            return;
+        }
+    }
+}
+
+/// Makes sure the relays are opened if the application panics
+pub fn install_panic_hook() {
+    let previous = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        emergency_off();
+        previous(info);
+    }));
+}
@@ -1,11 +1,15 @@
+use super::audit;
+use crate::logging;
 use crate::model::Model;
 use chrono::{Datelike, Timelike, Utc};
-use std::fs::{create_dir_all, File};
+use std::collections::BTreeMap;
+use std::fs::{create_dir_all, read_dir, remove_file, File};
 use std::io::prelude::*;
-
-use super::flashing::get_production_firmware_version;
+use std::path::{Path, PathBuf};
 
 const REPORTS_PATH: &str = "./reports";
+/// Length of the date and time starting the file names of a run
+const RUN_PREFIX: usize = "YYYYMMDD-HHMMSS".len();
 
 pub fn save_report(model: &Model) {
     create_dir_all(REPORTS_PATH).ok();
@@ -30,11 +34,13 @@ pub fn save_report(model: &Model) {
         }
     );
 
-    let mut file = File::create(filename).unwrap();
+    let mut file = File::create(&filename).unwrap();
 
-    let report = model
-        .report
-        .serializable(&model.config, get_production_firmware_version());
+    let version = model
+        .production_firmware()
+        .map(|image| image.versione.clone())
+        .unwrap_or_default();
+    let report = model.report.serializable(model.operator.as_ref(), version);
     //let content = serde_yaml::to_string::<SerializableReport>(&report).unwrap();
 
     let mut content: String = format!(
@@ -47,7 +53,9 @@ collaudo:
   versione: '{}'
   codice_dut: '{}'
   firmware: '{}'
-  hardware: ''
+  firmware_collaudo: '{}'
+  hardware: '{}'
+  uid_mcu: '{}'
   ordine_forn: {}
   fornitore: {}
   datario: '{}'
@@ -72,6 +80,9 @@ prove:
         report.collaudo.versione,
         report.collaudo.codice_dut,
         report.collaudo.firmware,
+        report.collaudo.firmware_collaudo,
+        report.collaudo.hardware,
+        report.collaudo.uid_mcu,
         report.collaudo.ordine_forn,
         report.collaudo.fornitore,
         report.collaudo.lotto_produzione,
@@ -110,8 +121,130 @@ prove:
         .as_str();
     }
 
+    if !model.report.lcd_segments.is_empty() {
+        content += "segmenti_lcd:\n";
+        for segment in &model.report.lcd_segments {
+            content += format!(
+                r#"- segmento: '{}'
+  esito: '{}'
+"#,
+                segment.name.replace("'", ""),
+                if segment.passed { "Pass" } else { "Fail" }
+            )
+            .as_str();
+        }
+    }
+
+    if !model.report.color_readings.is_empty() {
+        content += "retroilluminazione:\n";
+        for reading in &model.report.color_readings {
+            content += format!(
+                r#"- colore: '{}'
+  intensita: {}
+  rosso: {}
+  verde: {}
+  blu: {}
+  chiaro: {}
+  lux: {:.1}
+  esito: '{}'
+"#,
+                reading.light.name(),
+                reading.intensity,
+                reading.red,
+                reading.green,
+                reading.blue,
+                reading.clear,
+                reading.lux,
+                if reading.passed { "Pass" } else { "Fail" }
+            )
+            .as_str();
+        }
+    }
+
+    if !model.report.audit.is_empty() {
+        content += "registro_azioni:\n";
+        for entry in &model.report.audit {
+            content += audit::yaml(entry).as_str();
+        }
+    }
+
+    let base = filename.trim_end_matches(".yaml");
+    let log_path = format!("{}.log", base);
+    let log_name = Path::new(&log_path)
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+    content += format!("registro: '{}'\n", log_name).as_str();
+
     file.write_all(content.replace("\n", "\r\n").as_bytes())
         .ok();
+
+    let mut log = model.run_log.join("\n");
+    log += "\n";
+    if let Ok(mut file) = File::create(&log_path) {
+        file.write_all(log.replace("\n", "\r\n").as_bytes()).ok();
+    }
+
+    for attachment in &model.report.attachments {
+        if let Ok(mut file) = File::create(format!("{}-{}", base, attachment.name)) {
+            file.write_all(&attachment.content).ok();
+        }
+    }
+
+    enforce_size_limit(model.config.spazio_report, base);
+}
+
+/// Removes the oldest runs of the reports folder, each with its log and attachments, until it
+/// takes at most `megabytes`, sparing the run named `current`
+fn enforce_size_limit(megabytes: u64, current: &str) {
+    let limit = megabytes * 1024 * 1024;
+
+    let files: Vec<(PathBuf, u64)> = match read_dir(REPORTS_PATH) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| {
+                let metadata = entry.metadata().ok()?;
+                metadata.is_file().then(|| (entry.path(), metadata.len()))
+            })
+            .collect(),
+        Err(_) => return,
+    };
+
+    let mut total: u64 = files.iter().map(|(_, len)| len).sum();
+
+    // The files of a run start with its date and time, sorting the runs from the oldest
+    let run_of = |path: &Path| {
+        let name = path
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_default();
+        name.get(..RUN_PREFIX).unwrap_or(&name).to_string()
+    };
+    let mut runs: BTreeMap<String, Vec<(PathBuf, u64)>> = BTreeMap::new();
+    for (path, len) in files {
+        runs.entry(run_of(&path)).or_default().push((path, len));
+    }
+    let current = run_of(Path::new(current));
+
+    for (run, files) in runs {
+        if total <= limit {
+            return;
+        }
+        if run == current {
+            continue;
+        }
+
+        for (path, len) in files {
+            if remove_file(&path).is_ok() {
+                total -= len;
+            }
+        }
+        logging::info(
+            "report",
+            "Rimosso collaudo per lo spazio dei report",
+            &[("collaudo", &run)],
+        );
+    }
 }
 
 fn yaml_nullable<T>(value: Option<T>) -> String
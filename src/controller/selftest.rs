@@ -0,0 +1,181 @@
+use std::path::Path;
+use std::time::Duration;
+
+use rppal::gpio::Gpio;
+use tokio::time::sleep;
+
+use super::{
+    adc, frequency,
+    reles::{self, Rele},
+    worker,
+};
+use crate::model::{Configuration, FixtureCheck, ReleReadback, TestStep};
+
+const SPI_DEVICE: &str = "/dev/spidev0.0";
+
+/// Frequency generated for the PWM loopback check and the accepted error
+const LOOPBACK_FREQUENCY: u16 = 1000;
+const LOOPBACK_TOLERANCE: f64 = 0.1;
+const LOOPBACK_WINDOW: Duration = Duration::from_millis(200);
+
+/// Supply and full scale of the MCP3208, in V
+const ADC_REFERENCE: f64 = 3.35;
+
+const RELE_SETTLE: Duration = Duration::from_millis(100);
+/// Supply read with the supply relay closed and open, in V
+const SUPPLY_CLOSED: f64 = 10.0;
+const SUPPLY_OPEN: f64 = 1.0;
+
+/// Checks that the fixture works, without a board inserted
+pub async fn run(config: Configuration) -> Vec<FixtureCheck> {
+    reles::all_off();
+
+    let mut checks = vec![check_spi(), check_gpio(), check_vref()];
+
+    for rele in reles::fitted() {
+        let readback = config.verifica_rele.get(&format!("{:?}", rele)).copied();
+        checks.push(check_rele(rele, readback).await);
+    }
+
+    checks.push(check_pwm(config.pin_loopback_pwm).await);
+
+    reles::all_off();
+    checks
+}
+
+fn check_spi() -> FixtureCheck {
+    let result = if !Path::new(SPI_DEVICE).exists() {
+        Err(format!("{} non trovato", SPI_DEVICE))
+    } else {
+        adc::read_adc(adc::Channel::VRef)
+            .map(|_| String::from("ok"))
+            .map_err(|_| String::from("lettura ADC fallita"))
+    };
+
+    FixtureCheck::new("SPI / MCP3208", result)
+}
+
+fn check_gpio() -> FixtureCheck {
+    let result = Gpio::new()
+        .map(|_| String::from("ok"))
+        .map_err(|e| e.to_string());
+
+    FixtureCheck::new("GPIO", result)
+}
+
+fn check_vref() -> FixtureCheck {
+    let result = match worker::read_vref() {
+        Ok(vref) if TestStep::CheckVRef.check_limits(vref) => Ok(format!("{}V", vref)),
+        Ok(vref) => Err(format!("{}V fuori dai limiti", vref)),
+        Err(()) => Err("lettura fallita".into()),
+    };
+
+    FixtureCheck::new("Tensione di riferimento", result)
+}
+
+async fn check_rele(rele: Rele, readback: Option<ReleReadback>) -> FixtureCheck {
+    let name = format!("Rele' {:?}", rele);
+
+    let result = match (rele, readback) {
+        (_, Some(readback)) => check_readback(rele, readback).await,
+        // Without a board, only the supply relay has a known effect on the ADC
+        (Rele::CorrectPower, None) => check_supply_rele().await,
+        (_, None) => {
+            // Switched anyway to exercise its driver, but a relay that cannot be verified does
+            // not pass
+            let closed = switch(rele, true).await;
+            let open = switch(rele, false).await;
+            closed
+                .and(open)
+                .and(Err(String::from("livelli ADC di verifica non configurati")))
+        }
+    };
+
+    FixtureCheck::new(name.as_str(), result)
+}
+
+/// The channel of `readback` must read within its ranges with the relay closed and open
+async fn check_readback(rele: Rele, readback: ReleReadback) -> Result<String, String> {
+    let channel = adc::Channel::from_index(readback.canale)
+        .ok_or_else(|| format!("canale ADC {} inesistente", readback.canale))?;
+
+    let closed = switch(rele, true).await.and_then(|_| read_volts(channel));
+    let open = switch(rele, false).await.and_then(|_| read_volts(channel));
+    let (closed, open) = (closed?, open?);
+
+    let within = |value: f64, (min, max): (f64, f64)| value >= min && value <= max;
+    if !within(closed, readback.chiuso) {
+        Err(format!("{:.2}V con rele' chiuso", closed))
+    } else if !within(open, readback.aperto) {
+        Err(format!("{:.2}V con rele' aperto", open))
+    } else {
+        Ok(format!("{:.2}V chiuso, {:.2}V aperto", closed, open))
+    }
+}
+
+/// The supply must be present on the ADC with the relay closed and gone with the relay open
+async fn check_supply_rele() -> Result<String, String> {
+    let closed = switch(Rele::CorrectPower, true)
+        .await
+        .and_then(|_| read_supply());
+    let open = switch(Rele::CorrectPower, false)
+        .await
+        .and_then(|_| read_supply());
+    let (closed, open) = (closed?, open?);
+
+    if closed < SUPPLY_CLOSED {
+        Err(format!("alimentazione {:.2}V con rele' chiuso", closed))
+    } else if open > SUPPLY_OPEN {
+        Err(format!("alimentazione {:.2}V con rele' aperto", open))
+    } else {
+        Ok(format!(
+            "alimentazione {:.2}V chiuso, {:.2}V aperto",
+            closed, open
+        ))
+    }
+}
+
+/// Drives the relay and waits for its contacts to settle
+async fn switch(rele: Rele, value: bool) -> Result<(), String> {
+    // Switching without a board does not wear the contacts like a test does
+    reles::update_uncounted(rele, value)
+        .map_err(|_| String::from("impossibile pilotare il pin"))?;
+    sleep(RELE_SETTLE).await;
+    Ok(())
+}
+
+/// Voltage at the input of `channel`
+fn read_volts(channel: adc::Channel) -> Result<f64, String> {
+    let raw = adc::read_adc(channel).map_err(|_| String::from("lettura ADC fallita"))?;
+    Ok((raw as f64 / 4095.0) * ADC_REFERENCE)
+}
+
+fn read_supply() -> Result<f64, String> {
+    Ok(read_volts(adc::Channel::Supply)? * (13.43 / 1.43))
+}
+
+/// Generates a frequency and counts it back on `loopback_pin`, wired to the PWM output
+async fn check_pwm(loopback_pin: Option<u8>) -> FixtureCheck {
+    let pin = if let Some(pin) = loopback_pin {
+        pin
+    } else {
+        return FixtureCheck::new("PWM", Ok("loopback non configurato".into()));
+    };
+
+    let result = match frequency::start(LOOPBACK_FREQUENCY as f64).await {
+        Ok(()) => frequency::measure(pin, LOOPBACK_WINDOW).await,
+        Err(()) => Err("impossibile generare la frequenza".into()),
+    };
+    frequency::stop().await.ok();
+
+    let result = result.and_then(|found| {
+        let error = (found - LOOPBACK_FREQUENCY as f64).abs() / LOOPBACK_FREQUENCY as f64;
+        if error <= LOOPBACK_TOLERANCE {
+            Ok(format!("{:.0}Hz", found))
+        } else {
+            Err(format!("{:.0}Hz invece di {}Hz", found, LOOPBACK_FREQUENCY))
+        }
+    });
+
+    FixtureCheck::new("PWM", result)
+}
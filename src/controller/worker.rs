@@ -1,18 +1,26 @@
 use iced_native::{subscription, Subscription};
+use std::sync::Mutex;
 use std::time::{Duration, Instant};
 use tokio::sync::mpsc;
 use tokio::time::sleep;
-use tokio_modbus::{client::Context, slave::Slave};
-use tokio_serial::SerialStream;
+use tokio_modbus::client::Context;
 
 use crate::{
     controller::{
         adc,
         app::{ControllerEvent, ControllerMessage},
+        colorsensor,
         digiblock,
+        flashing::{self, Flasher, FlashingEvent},
+        frequency, lcd,
+        registers,
         reles::{self, Rele},
     },
-    model::TestStep,
+    logging,
+    model::{
+        Attachment, Barcode, ColorReading, ColorSensorKind, CurrentSense, FirmwareImage,
+        LcdPattern, LcdSegment, OptionBytes, RgbLight, TestStep,
+    },
 };
 
 use super::pwm;
@@ -20,19 +28,12 @@ use super::pwm;
 pub fn worker() -> Subscription<ControllerEvent> {
     struct SomeWorker;
 
+    /// Logs a message for the operator, the application adds it to the log panel
     async fn log(
         sender: &mut iced_futures::futures::channel::mpsc::Sender<ControllerEvent>,
         msg: impl Into<String> + std::fmt::Display,
     ) {
-        use chrono::prelude::*;
-
-        let now = Utc::now();
-        let time = now.format("%H:%M:%S:%3f");
-
-        sender
-            .send(ControllerEvent::Log(format!("[{}]: {}", time, msg)))
-            .await
-            .ok();
+        sender.send(ControllerEvent::Log(msg.into())).await.ok();
     }
 
     async fn frequency_test(
@@ -52,6 +53,33 @@ pub fn worker() -> Subscription<ControllerEvent> {
             .ok();
     }
 
+    async fn pulses_test(
+        ctx: &mut Context,
+        output: &mut iced_futures::futures::channel::mpsc::Sender<ControllerEvent>,
+        step: TestStep,
+        pulses: u16,
+    ) {
+        let (res, value) = match check_pulses(ctx, pulses).await {
+            Ok(PulsesOutcome::Counted(found)) => (
+                check_value_within(found as f64, step.limits()),
+                Some(found as f64),
+            ),
+            Ok(PulsesOutcome::GeneratorFault(fault)) => {
+                output
+                    .send(ControllerEvent::FixtureFault(step, fault))
+                    .await
+                    .ok();
+                return;
+            }
+            Err(()) => (false, None),
+        };
+
+        output
+            .send(ControllerEvent::TestResult(step, value, res))
+            .await
+            .ok();
+    }
+
     async fn analog_test(
         ctx: &mut Context,
         output: &mut iced_futures::futures::channel::mpsc::Sender<ControllerEvent>,
@@ -69,6 +97,98 @@ pub fn worker() -> Subscription<ControllerEvent> {
             .ok();
     }
 
+    async fn forward_flashing_events(
+        output: &mut iced_futures::futures::channel::mpsc::Sender<ControllerEvent>,
+        step: TestStep,
+        mut receiver: mpsc::UnboundedReceiver<FlashingEvent>,
+    ) {
+        while let Some(FlashingEvent::Progress(phase, progress)) = receiver.recv().await {
+            output
+                .send(ControllerEvent::FlashingProgress(step, phase, progress))
+                .await
+                .ok();
+        }
+    }
+
+    async fn flash(
+        output: &mut iced_futures::futures::channel::mpsc::Sender<ControllerEvent>,
+        step: TestStep,
+        image: FirmwareImage,
+        flasher: Box<dyn Flasher>,
+    ) {
+        let (sender, receiver) = mpsc::unbounded_channel();
+
+        let (outcome, ()) = tokio::join!(
+            flashing::load_firmware(flasher, image, sender),
+            forward_flashing_events(output, step, receiver)
+        );
+
+        if outcome.success && step == TestStep::FlashingTest {
+            // Restart into the test firmware
+            reset().await;
+        }
+
+        output
+            .send(ControllerEvent::FlashingOutput(
+                step,
+                outcome.output,
+                outcome.error,
+            ))
+            .await
+            .ok();
+        output
+            .send(ControllerEvent::TestResult(step, None, outcome.success))
+            .await
+            .ok();
+    }
+
+    async fn option_bytes(
+        output: &mut iced_futures::futures::channel::mpsc::Sender<ControllerEvent>,
+        options: OptionBytes,
+        flasher: Box<dyn Flasher>,
+    ) {
+        let step = TestStep::OptionBytes;
+        let (sender, receiver) = mpsc::unbounded_channel();
+
+        let ((outcome, register), ()) = tokio::join!(
+            flashing::write_option_bytes(flasher, options, sender),
+            forward_flashing_events(output, step, receiver)
+        );
+
+        output
+            .send(ControllerEvent::FlashingOutput(
+                step,
+                outcome.output,
+                outcome.error,
+            ))
+            .await
+            .ok();
+        output
+            .send(ControllerEvent::TestResult(
+                step,
+                register.map(|r| r as f64),
+                outcome.success,
+            ))
+            .await
+            .ok();
+    }
+
+    async fn current_test(
+        ctx: &mut Context,
+        output: &mut iced_futures::futures::channel::mpsc::Sender<ControllerEvent>,
+        step: TestStep,
+    ) {
+        let (res, value) = match check_current(ctx, step).await {
+            Ok(found) => (check_value_within(found, step.limits()), Some(found)),
+            Err(()) => (false, None),
+        };
+
+        output
+            .send(ControllerEvent::TestResult(step, value, res))
+            .await
+            .ok();
+    }
+
     use iced::futures::SinkExt;
     use tokio::time::timeout;
 
@@ -83,6 +203,7 @@ pub fn worker() -> Subscription<ControllerEvent> {
         |mut output| async move {
             let mut state = State::Disconnected;
             let mut timestamp = Instant::now();
+            let mut polling = PollingStats::default();
 
             let (sender, mut receiver) = mpsc::channel(32);
             output.send(ControllerEvent::Ready(sender)).await.ok();
@@ -97,16 +218,43 @@ pub fn worker() -> Subscription<ControllerEvent> {
                                 ControllerMessage::Connect(port) => {
                                     reset().await;
 
-                                    let builder = tokio_serial::new(port, 115200);
-                                    if let Ok(port) = SerialStream::open(&builder) {
-                                        if let Ok(ctx) = tokio_modbus::client::rtu::connect_slave(
-                                            port,
-                                            Slave(0x01),
+                                    if let Ok(mut ctx) = digiblock::connect(port.as_str()).await {
+                                        match timeout(
+                                            Duration::from_millis(200),
+                                            digiblock::get_info(&mut ctx),
                                         )
                                         .await
                                         {
-                                            state = State::Connected(ctx);
-                                            timestamp = Instant::now();
+                                            Ok(Ok(info)) => {
+                                                output
+                                                    .send(ControllerEvent::DeviceInfo(info))
+                                                    .await
+                                                    .ok();
+                                                // Buttons already pressed are detected before
+                                                // the first prompt
+                                                if let Ok(Ok(rsp)) = timeout(
+                                                    Duration::from_millis(50),
+                                                    digiblock::get_state(&mut ctx),
+                                                )
+                                                .await
+                                                {
+                                                    output
+                                                        .send(ControllerEvent::Update(rsp))
+                                                        .await
+                                                        .ok();
+                                                }
+                                                state = State::Connected(ctx);
+                                                timestamp = Instant::now();
+                                                polling = PollingStats::default();
+                                            }
+                                            _ => {
+                                                log(
+                                                    &mut output,
+                                                    "Impossibile leggere l'identita' del dispositivo",
+                                                )
+                                                .await;
+                                                ctx.disconnect().await.ok();
+                                            }
                                         }
                                     }
 
@@ -123,13 +271,51 @@ pub fn worker() -> Subscription<ControllerEvent> {
                                         .await
                                         .ok();
                                 }
+                                ControllerMessage::Flash(step, image, kind, chip) => {
+                                    let flasher = flashing::flasher(kind, chip);
+                                    flash(&mut output, step, image, flasher).await;
+                                }
+                                ControllerMessage::OptionBytes(options, kind, chip) => {
+                                    let flasher = flashing::flasher(kind, chip);
+                                    option_bytes(&mut output, options, flasher).await;
+                                }
                                 // Not connected, fail
+                                ControllerMessage::WriteManufacturingData(_) => {
+                                    output
+                                        .send(ControllerEvent::TestResult(
+                                            TestStep::WriteManufacturingData,
+                                            None,
+                                            false,
+                                        ))
+                                        .await
+                                        .ok();
+                                }
                                 ControllerMessage::Test(step) => {
                                     output
                                         .send(ControllerEvent::TestResult(step, None, false))
                                         .await
                                         .ok();
                                 }
+                                ControllerMessage::LcdCheck(_) => {
+                                    output
+                                        .send(ControllerEvent::TestResult(
+                                            TestStep::UiLCD,
+                                            None,
+                                            false,
+                                        ))
+                                        .await
+                                        .ok();
+                                }
+                                ControllerMessage::ColorCheck(light, ..) => {
+                                    output
+                                        .send(ControllerEvent::TestResult(
+                                            light.step(),
+                                            None,
+                                            false,
+                                        ))
+                                        .await
+                                        .ok();
+                                }
                                 _ => (),
                             }
                         }
@@ -142,9 +328,90 @@ pub fn worker() -> Subscription<ControllerEvent> {
                                 ControllerMessage::Disconnect => {
                                     ctx.disconnect().await.ok();
                                     state = State::Disconnected;
+                                    polling.trace();
+                                }
+                                ControllerMessage::Flash(step, image, kind, chip) => {
+                                    ctx.disconnect().await.ok();
+                                    state = State::Disconnected;
+                                    polling.trace();
+                                    let flasher = flashing::flasher(kind, chip);
+                                    flash(&mut output, step, image, flasher).await;
                                 }
-                                ControllerMessage::SetLight(light) => {
-                                    digiblock::set_light(ctx, light).await.ok();
+                                ControllerMessage::OptionBytes(options, kind, chip) => {
+                                    ctx.disconnect().await.ok();
+                                    state = State::Disconnected;
+                                    polling.trace();
+                                    let flasher = flashing::flasher(kind, chip);
+                                    option_bytes(&mut output, options, flasher).await;
+                                }
+                                ControllerMessage::SetLight(light, intensity) => {
+                                    digiblock::set_light(ctx, light, intensity).await.ok();
+                                }
+                                ControllerMessage::SetLcdPattern(pattern) => {
+                                    if digiblock::set_lcd_pattern(ctx, pattern).await.is_err() {
+                                        log(&mut output, "Impossibile comandare il pattern LCD")
+                                            .await;
+                                    }
+                                }
+                                ControllerMessage::PressButton(actuator) => {
+                                    press_button(actuator).await.ok();
+                                }
+                                ControllerMessage::ColorCheck(light, kind, levels, minimum) => {
+                                    let result =
+                                        check_backlight(ctx, light, kind, &levels, minimum).await;
+                                    let success = match result {
+                                        Ok(readings) => {
+                                            let passed = readings.iter().all(|r| r.passed);
+                                            output
+                                                .send(ControllerEvent::ColorResult(readings))
+                                                .await
+                                                .ok();
+                                            passed
+                                        }
+                                        Err(e) => {
+                                            log(&mut output, e).await;
+                                            false
+                                        }
+                                    };
+
+                                    output
+                                        .send(ControllerEvent::TestResult(
+                                            light.step(),
+                                            None,
+                                            success,
+                                        ))
+                                        .await
+                                        .ok();
+                                }
+                                ControllerMessage::LcdCheck(source) => {
+                                    let (result, images) = check_lcd(ctx, source.as_str()).await;
+                                    let success = match result {
+                                        Ok(segments) => {
+                                            let passed = segments.iter().all(|s| s.passed);
+                                            output
+                                                .send(ControllerEvent::LcdResult(segments, images))
+                                                .await
+                                                .ok();
+                                            passed
+                                        }
+                                        Err(e) => {
+                                            log(&mut output, e).await;
+                                            output
+                                                .send(ControllerEvent::LcdResult(vec![], images))
+                                                .await
+                                                .ok();
+                                            false
+                                        }
+                                    };
+
+                                    output
+                                        .send(ControllerEvent::TestResult(
+                                            TestStep::UiLCD,
+                                            None,
+                                            success,
+                                        ))
+                                        .await
+                                        .ok();
                                 }
                                 ControllerMessage::Test(TestStep::AnalogShortCircuit) => {
                                     let res =
@@ -159,6 +426,16 @@ pub fn worker() -> Subscription<ControllerEvent> {
                                         .await
                                         .ok();
                                 }
+                                ControllerMessage::Test(
+                                    step @ (TestStep::CurrentIdle
+                                    | TestStep::CurrentWhite
+                                    | TestStep::CurrentRed
+                                    | TestStep::CurrentGreen
+                                    | TestStep::CurrentBlue
+                                    | TestStep::CurrentOutput),
+                                ) => {
+                                    current_test(ctx, &mut output, step).await;
+                                }
                                 ControllerMessage::Test(TestStep::Analog) => {
                                     analog_test(ctx, &mut output, TestStep::Analog, 10).await;
                                 }
@@ -166,6 +443,9 @@ pub fn worker() -> Subscription<ControllerEvent> {
                                     frequency_test(ctx, &mut output, TestStep::Frequency, 2000)
                                         .await;
                                 }
+                                ControllerMessage::Test(TestStep::Pulses) => {
+                                    pulses_test(ctx, &mut output, TestStep::Pulses, 1000).await;
+                                }
                                 ControllerMessage::Test(TestStep::OutputShortCircuit) => {
                                     let res =
                                         check_output_short_circuit(ctx).await.unwrap_or(false);
@@ -179,6 +459,20 @@ pub fn worker() -> Subscription<ControllerEvent> {
                                         .await
                                         .ok();
                                 }
+                                ControllerMessage::WriteManufacturingData(barcode) => {
+                                    let res = write_manufacturing_data(ctx, &barcode)
+                                        .await
+                                        .unwrap_or(false);
+
+                                    output
+                                        .send(ControllerEvent::TestResult(
+                                            TestStep::WriteManufacturingData,
+                                            None,
+                                            res,
+                                        ))
+                                        .await
+                                        .ok();
+                                }
                                 ControllerMessage::Test(TestStep::Output) => {
                                     let res = check_output(ctx).await.unwrap_or(false);
 
@@ -210,9 +504,11 @@ pub fn worker() -> Subscription<ControllerEvent> {
                                     timeout(Duration::from_millis(50), digiblock::get_state(ctx))
                                         .await;
                                 if let Ok(Ok(rsp)) = result {
+                                    polling.add(now.elapsed());
                                     output.send(ControllerEvent::Update(rsp)).await.ok();
                                 } else {
                                     log(&mut output, "Errore di comunicazione").await;
+                                    polling.trace();
                                     output
                                         .send(ControllerEvent::TestResult(
                                             TestStep::Connecting,
@@ -232,6 +528,129 @@ pub fn worker() -> Subscription<ControllerEvent> {
     )
 }
 
+/// Timing of the state polling over Modbus, traced in the run log when the connection ends
+#[derive(Default)]
+struct PollingStats {
+    reads: u32,
+    total: Duration,
+    slowest: Duration,
+}
+
+impl PollingStats {
+    fn add(&mut self, elapsed: Duration) {
+        self.reads += 1;
+        self.total += elapsed;
+        self.slowest = self.slowest.max(elapsed);
+    }
+
+    fn trace(&self) {
+        let average = if self.reads > 0 {
+            self.total / self.reads
+        } else {
+            Duration::ZERO
+        };
+        logging::debug(
+            "modbus",
+            "Lettura dello stato",
+            &[
+                ("letture", &self.reads),
+                ("media_ms", &average.as_millis()),
+                ("massimo_ms", &self.slowest.as_millis()),
+            ],
+        );
+    }
+}
+
+/// Time given to the backlight to change colour before reading the sensor
+const BACKLIGHT_SETTLE_TIME: Duration = Duration::from_millis(200);
+
+/// Shows `light` at each intensity of `levels` and reads it with the colour sensor
+async fn check_backlight(
+    ctx: &mut Context,
+    light: RgbLight,
+    kind: ColorSensorKind,
+    levels: &[u8],
+    minimum_clear: u16,
+) -> Result<Vec<ColorReading>, String> {
+    let mut readings = Vec::new();
+
+    for &intensity in levels {
+        digiblock::set_light(ctx, light, intensity)
+            .await
+            .map_err(|_| String::from("Impossibile comandare la retroilluminazione"))?;
+        sleep(BACKLIGHT_SETTLE_TIME).await;
+
+        let mut reading =
+            tokio::task::spawn_blocking(move || colorsensor::read(kind, light, intensity))
+                .await
+                .map_err(|e| e.to_string())?
+                .map_err(|e| format!("Lettura sensore colore fallita: {}", e))?;
+        colorsensor::check(&mut reading, minimum_clear);
+        readings.push(reading);
+    }
+
+    // A colour passes on its readings, without any it would pass unchecked
+    if readings.is_empty() {
+        return Err("Nessun livello di retroilluminazione da verificare".into());
+    }
+
+    Ok(readings)
+}
+
+/// Time given to the LCD to show a new pattern before taking the picture
+const LCD_SETTLE_TIME: Duration = Duration::from_millis(500);
+
+/// Shows each pattern on the LCD and compares the pictures with the segment masks, returning
+/// the pictures taken even if the check could not be completed
+async fn check_lcd(
+    ctx: &mut Context,
+    source: &str,
+) -> (Result<Vec<LcdSegment>, String>, Vec<Attachment>) {
+    let masks = match lcd::load_masks() {
+        Ok(masks) => masks,
+        Err(e) => return (Err(e), vec![]),
+    };
+
+    let mut images = Vec::new();
+    let mut captures = Vec::new();
+
+    for pattern in [LcdPattern::AllOn, LcdPattern::AllOff] {
+        if digiblock::set_lcd_pattern(ctx, pattern).await.is_err() {
+            captures.push(Err(String::from("Impossibile comandare il pattern LCD")));
+            break;
+        }
+        sleep(LCD_SETTLE_TIME).await;
+
+        let capture = lcd::capture(source, pattern).await;
+        if let Ok(content) = &capture {
+            images.push(Attachment {
+                name: format!("lcd-{}.pgm", pattern.name()),
+                content: content.clone(),
+            });
+        }
+        captures.push(capture.and_then(|content| lcd::parse_pgm(&content)));
+    }
+
+    digiblock::set_lcd_pattern(ctx, LcdPattern::Normal).await.ok();
+
+    let result = match captures.into_iter().collect::<Result<Vec<_>, String>>() {
+        Ok(pictures) if pictures.len() == 2 => lcd::check(&masks, &pictures[0], &pictures[1]),
+        Ok(_) => Err(String::from("Immagini LCD mancanti")),
+        Err(e) => Err(e),
+    };
+
+    (result, images)
+}
+
+/// Time an actuator is kept closed for the board to register the press
+const BUTTON_PRESS_TIME: Duration = Duration::from_millis(300);
+
+async fn press_button(actuator: Rele) -> Result<(), ()> {
+    reles::update(actuator, true)?;
+    sleep(BUTTON_PRESS_TIME).await;
+    reles::update(actuator, false)
+}
+
 fn _get_ports() -> Vec<String> {
     serialport::available_ports()
         .unwrap_or(Vec::new())
@@ -259,45 +678,130 @@ pub async fn check_power_inversion() -> Result<f64, ()> {
     }
 }
 
+/// Current sense of this fixture, the default one until configured
+static CURRENT_SENSE: Mutex<Option<CurrentSense>> = Mutex::new(None);
+
+/// Sets the shunt and amplifier gain the supply current is computed from
+pub fn configure_current_sense(sense: CurrentSense) {
+    *CURRENT_SENSE.lock().unwrap_or_else(|e| e.into_inner()) = Some(sense);
+}
+
+/// Supply current in mA, averaged over a few samples
+pub fn read_current() -> Result<f64, ()> {
+    const SAMPLES: u16 = 8;
+
+    let sense = CURRENT_SENSE
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .unwrap_or_default();
+
+    let mut total: f64 = 0.0;
+    for _ in 0..SAMPLES {
+        total += adc::read_adc(adc::Channel::PowerConsumption).map_err(|_| ())? as f64;
+    }
+    let volts = ((total / SAMPLES as f64) / 4095.0) * 3.35;
+
+    Ok(((volts * sense.milliamperes_per_volt()) * 10.0).round() / 10.0)
+}
+
+/// Reference voltage of the fixture, in V
+pub fn read_vref() -> Result<f64, ()> {
+    let res = adc::read_adc(adc::Channel::VRef).map_err(|_| ())?;
+    let vref = (res as f64 / 4095.0) * 3.35;
+    Ok((vref * 100.0).round() / 100.0)
+}
+
+/// Voltage on the pressure sensor input, in V
+pub fn read_pressure() -> Result<f64, ()> {
+    let res = adc::read_adc(adc::Channel::Press).map_err(|_| ())?;
+    let pressure = ((res as f64 / 4095.0) * 3.35) * 2.0;
+    Ok((pressure * 100.0).round() / 100.0)
+}
+
 pub fn read_vbat() -> Result<f64, ()> {
     let res = adc::read_adc(adc::Channel::VBat).map_err(|_| ())?;
     Ok(res as f64)
 }
 
-async fn _check_pulses(ctx: &mut Context, pulses: u16) -> Result<u16, ()> {
+/// Period of the pulses counted by the board and the accepted deviation of each period
+const PULSE_PERIOD: Duration = Duration::from_micros(100);
+const PULSE_TOLERANCE: f64 = 0.25;
+/// Trains tried before blaming the fixture generator
+const PULSE_ATTEMPTS: u32 = 3;
+
+enum PulsesOutcome {
+    /// Pulses counted by the board, out of a regular train
+    Counted(u16),
+    /// The fixture could not emit a regular train, the board was not tested
+    GeneratorFault(String),
+}
+
+async fn check_pulses(ctx: &mut Context, pulses: u16) -> Result<PulsesOutcome, ()> {
     reles::update(Rele::AnalogMode, false).map_err(|_| ())?;
     reles::update(Rele::DigitalMode, true).map_err(|_| ())?;
 
     digiblock::set_frequency_mode(ctx).await.map_err(|_| ())?;
 
-    let mut counter = 0;
-    let result = loop {
-        counter += 1;
+    for attempt in 1..=PULSE_ATTEMPTS {
+        let mut counter = 0;
+        let result = loop {
+            counter += 1;
 
-        if counter > 5 {
-            break false;
+            if counter > 5 {
+                break false;
+            }
+
+            if let Ok(_) =
+                tokio::time::timeout(Duration::from_millis(50), digiblock::reset_pulses(ctx)).await
+            {
+                break true;
+            } else {
+            }
+        };
+
+        if !result {
+            return Err(());
         }
 
-        if let Ok(_) =
-            tokio::time::timeout(Duration::from_millis(50), digiblock::_reset_pulses(ctx)).await
-        {
-            break true;
-        } else {
+        let report = match pwm::pulse_train(pulses, PULSE_PERIOD).await {
+            Ok(report) => report,
+            Err(()) => {
+                let fault = String::from("uscita degli impulsi non disponibile");
+                return Ok(PulsesOutcome::GeneratorFault(fault));
+            }
+        };
+        logging::debug(
+            "worker",
+            "Treno di impulsi",
+            &[
+                ("tentativo", &attempt),
+                ("impulsi", &report.count),
+                ("impulsi_corti", &report.short_pulses),
+                ("durata", &format!("{:?}", report.duration)),
+                ("errore_massimo", &format!("{:?}", report.max_period_error)),
+            ],
+        );
+
+        if report.count == pulses && report.regular(PULSE_PERIOD, PULSE_TOLERANCE) {
+            let rsp = tokio::time::timeout(Duration::from_millis(50), digiblock::get_state(ctx))
+                .await
+                .map_err(|_| ())?
+                .map_err(|_| ())?;
+
+            return Ok(PulsesOutcome::Counted(rsp.pulses));
         }
-    };
 
-    if !result {
-        return Err(());
+        logging::warning(
+            "worker",
+            "Treno di impulsi irregolare",
+            &[("tentativo", &attempt)],
+        );
     }
 
-    pwm::toggle_times(pulses).await.map_err(|_| ())?;
-
-    let rsp = tokio::time::timeout(Duration::from_millis(50), digiblock::get_state(ctx))
-        .await
-        .map_err(|_| ())?
-        .map_err(|_| ())?;
-
-    Ok(rsp.pulses)
+    Ok(PulsesOutcome::GeneratorFault(format!(
+        "treno di impulsi irregolare in {} tentativi",
+        PULSE_ATTEMPTS
+    )))
 }
 
 async fn check_analog_short_circuit(ctx: &mut Context) -> Result<bool, ()> {
@@ -337,7 +841,7 @@ async fn check_analog(ctx: &mut Context, ma420: i32) -> Result<f64, ()> {
         .map_err(|_| ())?
         .map_err(|_| ())?;
 
-    let resulting_420ma = (rsp.ma420 as f64) / 100.0;
+    let resulting_420ma = registers::input::MA420.scaled(rsp.ma420);
 
     Ok(resulting_420ma)
 }
@@ -391,10 +895,58 @@ async fn toggle_output(ctx: &mut Context) -> Result<bool, ()> {
     }
 }
 
+async fn write_manufacturing_data(ctx: &mut Context, barcode: &Barcode) -> Result<bool, ()> {
+    tokio::time::timeout(
+        Duration::from_millis(200),
+        digiblock::write_manufacturing_data(ctx, barcode),
+    )
+    .await
+    .map_err(|_| ())??;
+
+    // Give the firmware time to write the non volatile memory
+    sleep(Duration::from_millis(500)).await;
+
+    let written = tokio::time::timeout(
+        Duration::from_millis(200),
+        digiblock::read_manufacturing_data(ctx),
+    )
+    .await
+    .map_err(|_| ())??;
+
+    Ok(digiblock::manufacturing_fields(barcode)
+        .iter()
+        .zip(written.iter())
+        .all(|(expected, found)| expected.trim() == found.trim()))
+}
+
+async fn check_current(ctx: &mut Context, step: TestStep) -> Result<f64, ()> {
+    digiblock::set_output(ctx, false).await?;
+
+    match step {
+        TestStep::CurrentWhite => digiblock::set_light(ctx, RgbLight::White, 100).await?,
+        TestStep::CurrentRed => digiblock::set_light(ctx, RgbLight::Red, 100).await?,
+        TestStep::CurrentGreen => digiblock::set_light(ctx, RgbLight::Green, 100).await?,
+        TestStep::CurrentBlue => digiblock::set_light(ctx, RgbLight::Blue, 100).await?,
+        TestStep::CurrentOutput => {
+            digiblock::set_backlight_off(ctx).await?;
+            digiblock::set_output(ctx, true).await?;
+        }
+        _ => digiblock::set_backlight_off(ctx).await?,
+    }
+
+    sleep(Duration::from_millis(300)).await;
+    let current = read_current();
+
+    digiblock::set_output(ctx, false).await.ok();
+    digiblock::set_backlight_off(ctx).await.ok();
+
+    current
+}
+
 async fn check_frequency(ctx: &mut Context, frequency: u16) -> Result<f64, ()> {
     reles::update(Rele::DigitalMode, true).map_err(|_| ())?;
     digiblock::set_frequency_mode(ctx).await.map_err(|_| ())?;
-    pwm::set_frequency(frequency).await.map_err(|_| ())?;
+    frequency::start(frequency as f64).await?;
     sleep(Duration::from_millis(500)).await;
 
     let rsp = tokio::time::timeout(Duration::from_millis(50), digiblock::get_state(ctx))
@@ -408,8 +960,7 @@ async fn check_frequency(ctx: &mut Context, frequency: u16) -> Result<f64, ()> {
         1_000_000.0 / (rsp.period_us as f64)
     };
 
-
-    pwm::toggle_times(0).await.map_err(|_| ())?;
+    frequency::stop().await?;
 
     Ok(found)
 }
@@ -0,0 +1,187 @@
+// Structured logging. Every record goes to the console, to a rotating file and to the log of
+// the current run; the log panel of the operator only gets the records from `ui_level` up
+use std::fmt::Display;
+use std::fs::{self, File, OpenOptions};
+use std::io::prelude::*;
+use std::sync::Mutex;
+
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+
+const LOGS_PATH: &str = "./logs";
+const LOG_FILE: &str = "./logs/tester.log";
+/// Size beyond which the file is rotated
+const MAX_FILE_SIZE: u64 = 5 * 1024 * 1024;
+/// Rotated files kept, as `tester.log.1` (the newest) to `tester.log.5`
+const ROTATED_FILES: u32 = 5;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Level {
+    Debug,
+    #[default]
+    Info,
+    Warning,
+    Error,
+}
+
+impl Level {
+    pub fn name(self: &Self) -> &'static str {
+        match self {
+            Level::Debug => "DEBUG",
+            Level::Info => "INFO",
+            Level::Warning => "WARNING",
+            Level::Error => "ERROR",
+        }
+    }
+}
+
+struct Record<'a> {
+    time: DateTime<Local>,
+    level: Level,
+    module: &'static str,
+    message: String,
+    fields: &'a [(&'a str, &'a dyn Display)],
+}
+
+impl<'a> Record<'a> {
+    fn fields(&self) -> String {
+        self.fields
+            .iter()
+            .map(|(key, value)| format!(" {}={}", key, value))
+            .collect()
+    }
+
+    /// Full line, for the console, the file and the run log
+    fn line(&self) -> String {
+        format!(
+            "{} {:<7} {}: {}{}",
+            self.time.format("%Y-%m-%d %H:%M:%S%.3f"),
+            self.level.name(),
+            self.module,
+            self.message,
+            self.fields()
+        )
+    }
+
+    /// Short line for the operator
+    fn panel_line(&self) -> String {
+        format!(
+            "[{}]: {}{}",
+            self.time.format("%H:%M:%S%.3f"),
+            self.message,
+            self.fields()
+        )
+    }
+}
+
+struct Logger {
+    ui_level: Level,
+    file: Option<File>,
+    file_size: u64,
+    /// Lines waiting to be collected by `take`
+    panel: Vec<String>,
+    run: Vec<String>,
+}
+
+static LOGGER: Mutex<Logger> = Mutex::new(Logger {
+    ui_level: Level::Info,
+    file: None,
+    file_size: 0,
+    panel: Vec::new(),
+    run: Vec::new(),
+});
+
+impl Logger {
+    fn write_file(&mut self, line: &str) {
+        if self.file.is_none() || self.file_size >= MAX_FILE_SIZE {
+            self.open_file();
+        }
+
+        if let Some(file) = &mut self.file {
+            if writeln!(file, "{}", line).is_ok() {
+                self.file_size += line.len() as u64 + 1;
+            }
+        }
+    }
+
+    fn open_file(&mut self) {
+        self.file = None;
+        fs::create_dir_all(LOGS_PATH).ok();
+
+        let size = fs::metadata(LOG_FILE).map(|m| m.len()).unwrap_or(0);
+        if size >= MAX_FILE_SIZE {
+            for index in (1..ROTATED_FILES).rev() {
+                let from = format!("{}.{}", LOG_FILE, index);
+                fs::rename(&from, format!("{}.{}", LOG_FILE, index + 1)).ok();
+            }
+            fs::rename(LOG_FILE, format!("{}.1", LOG_FILE)).ok();
+        }
+
+        match OpenOptions::new().create(true).append(true).open(LOG_FILE) {
+            Ok(file) => {
+                self.file_size = file.metadata().map(|m| m.len()).unwrap_or(0);
+                self.file = Some(file);
+            }
+            Err(e) => eprintln!("Unable to open {}: {}", LOG_FILE, e),
+        }
+    }
+}
+
+fn lock() -> std::sync::MutexGuard<'static, Logger> {
+    LOGGER.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Sets the lowest level shown to the operator
+pub fn configure(ui_level: Level) {
+    lock().ui_level = ui_level;
+}
+
+pub fn log(
+    level: Level,
+    module: &'static str,
+    message: impl Display,
+    fields: &[(&str, &dyn Display)],
+) {
+    let record = Record {
+        time: Local::now(),
+        level,
+        module,
+        message: message.to_string(),
+        fields,
+    };
+    let line = record.line();
+
+    let mut logger = lock();
+    println!("{}", line);
+    logger.write_file(line.as_str());
+    if level >= logger.ui_level {
+        logger.panel.push(record.panel_line());
+    }
+    logger.run.push(line);
+}
+
+pub fn debug(module: &'static str, message: impl Display, fields: &[(&str, &dyn Display)]) {
+    log(Level::Debug, module, message, fields)
+}
+
+pub fn info(module: &'static str, message: impl Display, fields: &[(&str, &dyn Display)]) {
+    log(Level::Info, module, message, fields)
+}
+
+pub fn warning(module: &'static str, message: impl Display, fields: &[(&str, &dyn Display)]) {
+    log(Level::Warning, module, message, fields)
+}
+
+pub fn error(module: &'static str, message: impl Display, fields: &[(&str, &dyn Display)]) {
+    log(Level::Error, module, message, fields)
+}
+
+/// Lines produced since the last call, for the log panel and for the run log
+pub fn take() -> (Vec<String>, Vec<String>) {
+    let mut logger = lock();
+    (
+        std::mem::take(&mut logger.panel),
+        std::mem::take(&mut logger.run),
+    )
+}
@@ -1,6 +1,7 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")] // hide console window on Windows in release
 
 mod controller;
+mod logging;
 mod model;
 mod view;
 
@@ -8,10 +9,25 @@ use iced;
 use iced::Application;
 
 fn main() -> iced::Result {
+    // Prints the record of a PIN for `operators.yaml`, without starting the tester
+    let args: Vec<String> = std::env::args().collect();
+    if let [_, flag, pin] = args.as_slice() {
+        if flag == "--hash-pin" {
+            match controller::operators::hash_pin(pin) {
+                Ok(record) => println!("{}", record),
+                Err(e) => eprintln!("{}", e),
+            }
+            return Ok(());
+        }
+    }
+
+    controller::reles::install_panic_hook();
     controller::reles::all_off();
 
     controller::app::App::run(iced::Settings {
         default_text_size: 32.0,
+        // Closing is handled by the application, so that the relays are opened first
+        exit_on_close_request: false,
         ..iced::Settings::default()
     })
 }
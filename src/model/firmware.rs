@@ -0,0 +1,111 @@
+use serde::{Deserialize, Serialize};
+
+/// Firmware images available to the tester, described by `binaries/firmware.yaml`
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct FirmwareManifest {
+    pub collaudo: FirmwareImage,
+    pub produzione: Vec<FirmwareImage>,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct FirmwareImage {
+    /// Hex file, relative to the binaries folder
+    pub file: String,
+    #[serde(default)]
+    pub versione: String,
+    pub sha256: String,
+    /// Openocd configuration used to program this image, relative to the binaries folder.
+    /// Only needed with the openocd programmer
+    #[serde(default)]
+    pub openocd: String,
+    /// Variants this image can be loaded on; empty means every variant
+    #[serde(default)]
+    pub varianti: Vec<String>,
+    /// Option bytes to program after this image, if any
+    #[serde(default)]
+    pub option_bytes: Option<OptionBytes>,
+}
+
+/// Option bytes programmed after the production firmware, following the STM32G0 `FLASH_OPTR`
+/// layout
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct OptionBytes {
+    /// Readout protection level, 0 or 1. Level 2 is permanent and is refused
+    pub rdp: u8,
+    /// Brown-out reset threshold from 0 to 3; absent leaves the brown-out reset disabled
+    #[serde(default)]
+    pub bor: Option<u8>,
+    /// Start the independent watchdog by hardware at reset
+    #[serde(default)]
+    pub iwdg_hw: bool,
+    /// Start the window watchdog by hardware at reset
+    #[serde(default)]
+    pub wwdg_hw: bool,
+    /// Openocd configuration connecting to the target without programming it
+    #[serde(default)]
+    pub openocd: String,
+}
+
+impl OptionBytes {
+    const RDP_LEVEL_0: u32 = 0xAA;
+    const RDP_LEVEL_1: u32 = 0xBB;
+    const RDP_MASK: u32 = 0xFF;
+    const BOR_EN: u32 = 1 << 8;
+    const BORF_LEV_SHIFT: u32 = 9;
+    const BORR_LEV_SHIFT: u32 = 11;
+    const IWDG_SW: u32 = 1 << 16;
+    const WWDG_SW: u32 = 1 << 19;
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.rdp > 1 {
+            Err(format!("Livello di protezione {} non ammesso", self.rdp))
+        } else if self.bor.map(|b| b > 3).unwrap_or(false) {
+            Err(format!("Livello BOR {} non valido", self.bor.unwrap_or(0)))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Value and mask of the bits of `FLASH_OPTR` managed by the tester
+    pub fn register(&self) -> (u32, u32) {
+        let mask = Self::RDP_MASK
+            | Self::BOR_EN
+            | (0x3 << Self::BORF_LEV_SHIFT)
+            | (0x3 << Self::BORR_LEV_SHIFT)
+            | Self::IWDG_SW
+            | Self::WWDG_SW;
+
+        let mut value = if self.rdp == 0 {
+            Self::RDP_LEVEL_0
+        } else {
+            Self::RDP_LEVEL_1
+        };
+
+        if let Some(bor) = self.bor {
+            let bor = (bor & 0x3) as u32;
+            value |= Self::BOR_EN | (bor << Self::BORF_LEV_SHIFT) | (bor << Self::BORR_LEV_SHIFT);
+        }
+
+        // The watchdog bits select software start when set
+        if !self.iwdg_hw {
+            value |= Self::IWDG_SW;
+        }
+        if !self.wwdg_hw {
+            value |= Self::WWDG_SW;
+        }
+
+        (value, mask)
+    }
+}
+
+impl FirmwareImage {
+    pub fn allows_variant(&self, variant: &str) -> bool {
+        self.varianti.is_empty() || self.varianti.iter().any(|v| v == variant.trim())
+    }
+}
+
+impl FirmwareManifest {
+    pub fn production_for(&self, variant: &str) -> Option<&FirmwareImage> {
+        self.produzione.iter().find(|i| i.allows_variant(variant))
+    }
+}
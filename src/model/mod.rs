@@ -1,5 +1,9 @@
+mod firmware;
 mod model;
+mod operator;
 mod report;
 
+pub use firmware::*;
 pub use model::*;
+pub use operator::*;
 pub use report::*;
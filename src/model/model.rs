@@ -1,8 +1,14 @@
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Instant;
 
-use super::Report;
+use crate::logging::{self, Level};
 
-#[derive(Clone, Copy, Default, Debug)]
+use super::{FirmwareImage, FirmwareManifest, Operator, OperatorRegistry, Permission, Report};
+
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq)]
 pub enum RgbLight {
     #[default]
     White,
@@ -11,6 +17,53 @@ pub enum RgbLight {
     Blue,
 }
 
+impl RgbLight {
+    pub const ALL: [RgbLight; 4] = [
+        RgbLight::White,
+        RgbLight::Red,
+        RgbLight::Green,
+        RgbLight::Blue,
+    ];
+
+    /// Step checking this colour
+    pub fn step(self: &Self) -> TestStep {
+        match self {
+            RgbLight::White => TestStep::UiRgbWhite,
+            RgbLight::Red => TestStep::UiRgbRed,
+            RgbLight::Green => TestStep::UiRgbGreen,
+            RgbLight::Blue => TestStep::UiRgbBlue,
+        }
+    }
+
+    pub fn name(self: &Self) -> &'static str {
+        match self {
+            RgbLight::White => "bianco",
+            RgbLight::Red => "rosso",
+            RgbLight::Green => "verde",
+            RgbLight::Blue => "blu",
+        }
+    }
+
+    /// Accepted share of red, green and blue in the light measured by the colour sensor
+    pub fn chromaticity_limits(self: &Self) -> [(f64, f64); 3] {
+        match self {
+            RgbLight::White => [(0.2, 0.5), (0.2, 0.5), (0.2, 0.5)],
+            RgbLight::Red => [(0.6, 1.0), (0.0, 0.25), (0.0, 0.25)],
+            RgbLight::Green => [(0.0, 0.25), (0.5, 1.0), (0.0, 0.35)],
+            RgbLight::Blue => [(0.0, 0.25), (0.0, 0.35), (0.5, 1.0)],
+        }
+    }
+}
+
+/// Colour sensor measuring the backlight
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ColorSensorKind {
+    Tcs34725,
+    /// Readings matching the commanded colour, for runs without the sensor
+    Simulato,
+}
+
 #[derive(Clone, Copy, Default)]
 pub enum StepState {
     #[default]
@@ -29,32 +82,196 @@ pub struct DigiblockState {
     pub short_circuit_out: bool,
 }
 
+#[derive(Debug, Clone, Default)]
+pub struct DigiblockInfo {
+    pub firmware_version: String,
+    pub hardware_revision: u16,
+    pub uid: String,
+}
+
+impl DigiblockInfo {
+    pub fn matches_revision(&self, rev_hw: &str) -> bool {
+        rev_hw.trim().parse::<u16>().ok() == Some(self.hardware_revision)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum FlashingError {
+    ChecksumMismatch,
+    InvalidImage,
+    ProgrammerUnavailable,
+    OptionBytesMismatch,
+    TargetNotFound,
+    EraseFailed,
+    VerifyFailed,
+    ReadProtected,
+    Unknown,
+}
+
+impl FlashingError {
+    /// Error code suffix and description used in the report
+    pub fn metadata(self: &Self) -> (&'static str, &'static str) {
+        use FlashingError::*;
+        match self {
+            ChecksumMismatch => ("E01", "Checksum del firmware errato"),
+            ProgrammerUnavailable => ("E02", "Impossibile avviare il programmatore"),
+            TargetNotFound => ("E03", "Microcontrollore non trovato"),
+            EraseFailed => ("E04", "Cancellazione della flash fallita"),
+            VerifyFailed => ("E05", "Verifica della flash fallita"),
+            ReadProtected => ("E06", "Microcontrollore protetto in lettura"),
+            InvalidImage => ("E07", "File del firmware non valido"),
+            OptionBytesMismatch => ("E08", "Option byte letti diversi da quelli scritti"),
+            Unknown => ("E99", "Errore di programmazione sconosciuto"),
+        }
+    }
+}
+
 #[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
 pub enum TestStep {
+    CheckVRef,
     InvertPower,
     FlashingTest,
     Connecting,
     UiLeftButton,
     UiRightButton,
     UiLCD,
-    UiRgb,
+    UiRgbWhite,
+    UiRgbRed,
+    UiRgbGreen,
+    UiRgbBlue,
     Check3v3,
     Check5v,
     Check12v,
+    Pressure,
+    CurrentIdle,
+    CurrentWhite,
+    CurrentRed,
+    CurrentGreen,
+    CurrentBlue,
+    CurrentOutput,
     AnalogShortCircuit,
     Analog,
     Frequency,
+    Pulses,
     OutputShortCircuit,
     Output,
+    WriteManufacturingData,
     FlashingProduction,
+    OptionBytes,
+    VerifyProduction,
+}
+
+/// Content of the LCD commanded to the test firmware
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LcdPattern {
+    /// Whatever the test firmware shows on its own
+    Normal,
+    AllOn,
+    AllOff,
+    /// Every other segment on
+    Checkerboard,
+    /// The same digit, from 0 to 9, on every position
+    Digit(u8),
+}
+
+/// Pictures of what each pattern should look like, shown to the operator
+const LCD_PATTERN_IMAGES: &str = "./lcd/patterni";
+
+impl LcdPattern {
+    /// Patterns shown in turn to the operator when there is no camera
+    pub fn sequence() -> Vec<LcdPattern> {
+        let mut patterns = vec![
+            LcdPattern::AllOn,
+            LcdPattern::AllOff,
+            LcdPattern::Checkerboard,
+        ];
+        patterns.extend((0..10).map(LcdPattern::Digit));
+        patterns
+    }
+
+    pub fn code(self: &Self) -> u16 {
+        match self {
+            LcdPattern::Normal => 0,
+            LcdPattern::AllOn => 1,
+            LcdPattern::AllOff => 2,
+            LcdPattern::Checkerboard => 3,
+            LcdPattern::Digit(_) => 4,
+        }
+    }
+
+    /// Value of the digit register, only meaningful for `Digit`
+    pub fn digit(self: &Self) -> u16 {
+        match self {
+            LcdPattern::Digit(digit) => *digit as u16,
+            _ => 0,
+        }
+    }
+
+    pub fn name(self: &Self) -> String {
+        match self {
+            LcdPattern::Normal => "normale".into(),
+            LcdPattern::AllOn => "tutto-acceso".into(),
+            LcdPattern::AllOff => "tutto-spento".into(),
+            LcdPattern::Checkerboard => "scacchiera".into(),
+            LcdPattern::Digit(digit) => format!("cifra-{}", digit),
+        }
+    }
+
+    pub fn description(self: &Self) -> String {
+        match self {
+            LcdPattern::Normal => "Schermata del firmware di collaudo".into(),
+            LcdPattern::AllOn => "Tutti i segmenti accesi".into(),
+            LcdPattern::AllOff => "Tutti i segmenti spenti".into(),
+            LcdPattern::Checkerboard => "Segmenti accesi a scacchiera".into(),
+            LcdPattern::Digit(digit) => format!("La cifra {} su tutte le posizioni", digit),
+        }
+    }
+
+    /// Picture of the pattern, if one was provided
+    pub fn image(self: &Self) -> Option<PathBuf> {
+        let path = Path::new(LCD_PATTERN_IMAGES).join(format!("{}.png", self.name()));
+        if path.exists() {
+            Some(path)
+        } else {
+            None
+        }
+    }
 }
 
 #[derive(Clone, Default)]
 pub enum TestState {
     #[default]
     Ready,
+    SelfTest,
+    FixtureFault,
     Testing(TestStep, StepState),
     Done,
+    Maintenance,
+    /// Waiting for an operator to scan the badge or type the PIN
+    Login,
+}
+
+/// Outcome of a single check of the fixture self-test
+#[derive(Clone, Debug)]
+pub struct FixtureCheck {
+    pub name: String,
+    pub passed: bool,
+    pub detail: String,
+}
+
+impl FixtureCheck {
+    pub fn new(name: &str, result: Result<String, String>) -> Self {
+        let (passed, detail) = match result {
+            Ok(detail) => (true, detail),
+            Err(detail) => (false, detail),
+        };
+
+        Self {
+            name: name.into(),
+            passed,
+            detail,
+        }
+    }
 }
 
 #[derive(Clone, Default)]
@@ -62,42 +279,397 @@ pub struct Model {
     pub state: TestState,
     pub digiblock_state: DigiblockState,
     pub logs: Vec<String>,
-    pub light: RgbLight,
+    /// Complete timeline of the run, saved next to the report
+    pub run_log: Vec<String>,
+    /// Index of the backlight intensity shown, in `livelli_retroilluminazione`
+    pub light_level: usize,
+    /// Index of the LCD pattern shown to the operator, in `LcdPattern::sequence`
+    pub lcd_pattern: usize,
     pub report: Report,
     pub config: Configuration,
     pub vbat: Option<Vec<f64>>,
-    pub firmware_version: String,
+    /// Fixture reference voltage, read at startup and at the start of every run
+    pub vref: Option<f64>,
+    pub fixture_checks: Vec<FixtureCheck>,
+    pub firmware: Option<FirmwareManifest>,
+    pub firmware_error: Option<String>,
+    pub flashing: Option<FlashingProgress>,
+    /// Average flashing time in seconds of previous runs, by step code
+    pub flashing_times: HashMap<String, f64>,
+    pub maintenance: MaintenanceCounters,
+    /// `None` until the frequency generator of this fixture is calibrated
+    pub calibration: Option<Calibration>,
+    /// Outcome of the last frequency calibration requested from the maintenance screen
+    pub calibration_result: Option<Result<f64, String>>,
+    pub operators: OperatorRegistry,
+    pub operators_error: Option<String>,
+    /// Operator logged in, if any
+    pub operator: Option<Operator>,
+    /// Badge, operator code and PIN being typed on the login screen
+    pub login_badge: String,
+    pub login_code: String,
+    pub login_pin: String,
+    pub login_error: Option<String>,
+    /// Minimum and maximum being typed on the maintenance screen, for each step with limits
+    pub limit_inputs: Vec<(TestStep, String, String)>,
+    /// Outcome of the last save of the limits
+    pub limits_result: Option<Result<(), String>>,
+}
+
+/// Wear counters of the fixture, persisted across runs
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct MaintenanceCounters {
+    /// Number of times each relay was closed, by relay name
+    #[serde(default)]
+    pub rele: BTreeMap<String, u64>,
+    /// Boards inserted on the pogo pins
+    #[serde(default)]
+    pub inserimenti: u64,
+}
+
+/// Counts after which the fixture parts should be replaced
+#[derive(Clone, Serialize, Deserialize)]
+pub struct MaintenanceThresholds {
+    pub cicli_rele: u64,
+    pub inserimenti: u64,
+}
+
+impl Default for MaintenanceThresholds {
+    fn default() -> Self {
+        Self {
+            cicli_rele: 100_000,
+            inserimenti: 50_000,
+        }
+    }
+}
+
+/// Voltages expected on an ADC channel with a relay closed and open, without a board inserted
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct ReleReadback {
+    /// MCP3208 channel, from 0 to 7
+    pub canale: u8,
+    /// Accepted range at the ADC input in V, with the relay closed
+    pub chiuso: (f64, f64),
+    /// Accepted range at the ADC input in V, with the relay open
+    pub aperto: (f64, f64),
+}
+
+/// Supply current sense of the fixture: a shunt in the supply of the board followed by an
+/// amplifier, read on `PowerConsumption`
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct CurrentSense {
+    /// Shunt resistance in ohm
+    pub resistenza_shunt: f64,
+    /// Voltage gain of the amplifier after the shunt
+    pub guadagno: f64,
+}
+
+impl CurrentSense {
+    pub fn milliamperes_per_volt(self: &Self) -> f64 {
+        1000.0 / (self.resistenza_shunt * self.guadagno)
+    }
+}
+
+impl Default for CurrentSense {
+    /// Assumed values, not taken from the fixture schematic: set the real ones in `config.yaml`
+    fn default() -> Self {
+        Self {
+            resistenza_shunt: 3.3,
+            guadagno: 3.03,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum FlashingPhase {
+    Connect,
+    Erase,
+    Program,
+    Verify,
+}
+
+impl FlashingPhase {
+    /// Description and share of the overall flashing time as (start, end) fractions
+    pub fn metadata(self: &Self) -> (&'static str, f32, f32) {
+        use FlashingPhase::*;
+        match self {
+            Connect => ("Connessione", 0.0, 0.1),
+            Erase => ("Cancellazione", 0.1, 0.3),
+            Program => ("Scrittura", 0.3, 0.8),
+            Verify => ("Verifica", 0.8, 1.0),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct FlashingProgress {
+    pub phase: FlashingPhase,
+    /// Progress within the current phase, from 0 to 1
+    pub progress: f32,
+    pub start: Instant,
+}
+
+impl FlashingProgress {
+    pub fn new() -> Self {
+        Self {
+            phase: FlashingPhase::Connect,
+            progress: 0.0,
+            start: Instant::now(),
+        }
+    }
+
+    pub fn overall(&self) -> f32 {
+        let (_, start, end) = self.phase.metadata();
+        start + (end - start) * self.progress.clamp(0.0, 1.0)
+    }
 }
 
 #[derive(Clone, Serialize, Deserialize)]
 pub struct Configuration {
-    pub operatore: u8,
+    /// Operator of the fixtures without `operators.yaml`, logged in with the lowest role
+    #[serde(default)]
+    pub operatore: Option<u8>,
+    /// Restart the board after programming and check that the production firmware comes up
+    #[serde(default)]
+    pub verifica_firmware_produzione: bool,
+    /// Input pin wired to the PWM output, for the fixture self-test
+    #[serde(default)]
+    pub pin_loopback_pwm: Option<u8>,
+    /// Variants fitted with the pressure sensor input
+    #[serde(default)]
+    pub varianti_pressione: Vec<String>,
+    #[serde(default)]
+    pub programmatore: FlasherKind,
+    /// Camera checking the LCD: a V4L2 device (`/dev/video0`) or a directory of still images,
+    /// one `<pattern>.pgm` for each pattern. The operator checks the LCD if missing
+    #[serde(default)]
+    pub camera: Option<String>,
+    /// Colour sensor checking the backlight, the operator checks it if missing
+    #[serde(default)]
+    pub sensore_colore: Option<ColorSensorKind>,
+    /// Backlight intensities, in percent, shown for each colour
+    #[serde(default = "default_livelli_retroilluminazione")]
+    pub livelli_retroilluminazione: Vec<u8>,
+    /// Minimum clear channel count for a backlight colour at full intensity to be considered lit
+    #[serde(default = "default_intensita_minima")]
+    pub intensita_minima: u16,
+    /// Target name for the probe-rs programmer
+    #[serde(default)]
+    pub chip: Option<String>,
+    #[serde(default)]
+    pub soglie_manutenzione: MaintenanceThresholds,
+    #[serde(default)]
+    pub sensore_corrente: CurrentSense,
+    #[serde(default)]
+    pub generatore_frequenza: FrequencyGeneratorKind,
+    /// Seconds given to the operator to press each button
+    #[serde(default = "default_timeout_tasti")]
+    pub timeout_tasti: u64,
+    /// The fixture has solenoids pressing the buttons
+    #[serde(default)]
+    pub attuatori_tasti: bool,
+    /// ADC levels read back by the self-test for each relay, by relay name
+    #[serde(default)]
+    pub verifica_rele: BTreeMap<String, ReleReadback>,
+    /// Megabytes the reports folder may take, the oldest files are removed beyond it
+    #[serde(default = "default_spazio_report")]
+    pub spazio_report: u64,
+    /// Lowest level of the messages shown in the log panel
+    #[serde(default)]
+    pub livello_log: Level,
+}
+
+fn default_timeout_tasti() -> u64 {
+    30
+}
+
+fn default_spazio_report() -> u64 {
+    2048
+}
+
+fn default_intensita_minima() -> u16 {
+    200
+}
+
+fn default_livelli_retroilluminazione() -> Vec<u8> {
+    vec![100]
+}
+
+/// Backend generating the frequency read by the board in digital mode
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum FrequencyGeneratorKind {
+    /// Hardware PWM driven directly, corrected by the fixture calibration
+    #[default]
+    Pwm,
+    /// Hardware PWM through the pigpio daemon
+    Pigpio,
+}
+
+/// Corrections measured on this fixture, persisted separately from the configuration
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Calibration {
+    /// Factor applied to the requested frequency
+    pub correzione_frequenza: f64,
+}
+
+impl Default for Calibration {
+    /// No correction
+    fn default() -> Self {
+        Self {
+            correzione_frequenza: 1.0,
+        }
+    }
+}
+
+/// Backend used to program the board
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum FlasherKind {
+    #[default]
+    Openocd,
+    StFlash,
+    ProbeRs,
+}
+
+impl FlasherKind {
+    pub fn name(self: &Self) -> &'static str {
+        match self {
+            FlasherKind::Openocd => "openocd",
+            FlasherKind::StFlash => "st-flash",
+            FlasherKind::ProbeRs => "probe-rs",
+        }
+    }
 }
 
 impl Default for Configuration {
     fn default() -> Self {
-        Self { operatore: 1 }
+        Self {
+            operatore: None,
+            verifica_firmware_produzione: false,
+            varianti_pressione: Vec::new(),
+            pin_loopback_pwm: None,
+            programmatore: FlasherKind::default(),
+            chip: None,
+            camera: None,
+            sensore_colore: None,
+            intensita_minima: default_intensita_minima(),
+            livelli_retroilluminazione: default_livelli_retroilluminazione(),
+            soglie_manutenzione: MaintenanceThresholds::default(),
+            sensore_corrente: CurrentSense::default(),
+            generatore_frequenza: FrequencyGeneratorKind::default(),
+            timeout_tasti: default_timeout_tasti(),
+            attuatori_tasti: false,
+            verifica_rele: BTreeMap::new(),
+            spazio_report: default_spazio_report(),
+            livello_log: Level::default(),
+        }
     }
 }
 
+/// Limits of a measured step, changed from the maintenance screen
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct StepLimits {
+    pub minimo: f64,
+    pub massimo: f64,
+}
+
+/// Limits overriding the default ones on this fixture, by step code
+static LIMIT_OVERRIDES: Mutex<BTreeMap<String, StepLimits>> = Mutex::new(BTreeMap::new());
+
+/// Replaces the limits changed on this fixture
+pub fn override_limits(overrides: BTreeMap<String, StepLimits>) {
+    *LIMIT_OVERRIDES.lock().unwrap_or_else(|e| e.into_inner()) = overrides;
+}
+
 impl TestStep {
+    /// Every step, in the order of the report
+    pub const ALL: [TestStep; 31] = [
+        TestStep::CheckVRef,
+        TestStep::InvertPower,
+        TestStep::FlashingTest,
+        TestStep::Connecting,
+        TestStep::UiLeftButton,
+        TestStep::UiRightButton,
+        TestStep::UiLCD,
+        TestStep::UiRgbWhite,
+        TestStep::UiRgbRed,
+        TestStep::UiRgbGreen,
+        TestStep::UiRgbBlue,
+        TestStep::Check3v3,
+        TestStep::Check5v,
+        TestStep::Check12v,
+        TestStep::Pressure,
+        TestStep::CurrentIdle,
+        TestStep::CurrentWhite,
+        TestStep::CurrentRed,
+        TestStep::CurrentGreen,
+        TestStep::CurrentBlue,
+        TestStep::CurrentOutput,
+        TestStep::AnalogShortCircuit,
+        TestStep::Analog,
+        TestStep::Frequency,
+        TestStep::Pulses,
+        TestStep::OutputShortCircuit,
+        TestStep::Output,
+        TestStep::WriteManufacturingData,
+        TestStep::FlashingProduction,
+        TestStep::OptionBytes,
+        TestStep::VerifyProduction,
+    ];
+
     pub fn metadata(self: &Self) -> (&'static str, &'static str, &'static str) {
         use TestStep::*;
         match self {
+            CheckVRef => (
+                "A0022",
+                "Verifica della tensione di riferimento dell'attrezzatura",
+                "V",
+            ),
             InvertPower => ("A001", "Inversione della tensione", ""),
             FlashingTest => ("A002", "Caricamento firmware di collaudo", ""),
             Connecting => ("A003", "Connessione USB", ""),
             UiLeftButton => ("M001", "Verifica del funzionamento del tasto sinistro", ""),
             UiRightButton => ("M002", "Verifica del funzionamento del tasto destro", ""),
             UiLCD => ("M003", "Verifica del funzionamento dei segmenti", ""),
-            UiRgb => (
-                "M004",
-                "Verifica del funzionamento della retroilluminazione",
-                "",
-            ),
+            UiRgbWhite => ("M004", "Verifica della retroilluminazione bianca", ""),
+            UiRgbRed => ("M005", "Verifica della retroilluminazione rossa", ""),
+            UiRgbGreen => ("M006", "Verifica della retroilluminazione verde", ""),
+            UiRgbBlue => ("M007", "Verifica della retroilluminazione blu", ""),
             Check3v3 => ("A004", "Verifica del corretto livello della linea 3v3", "V"),
             Check5v => ("A005", "Verifica del corretto livello della linea 5v", "V"),
             Check12v => ("A006", "Verifica del corretto livello della linea 12v", "V"),
+            Pressure => (
+                "A0023",
+                "Verifica dell'ingresso del sensore di pressione",
+                "V",
+            ),
+            CurrentIdle => ("A0016", "Misura dell'assorbimento a riposo", "mA"),
+            CurrentWhite => (
+                "A0017",
+                "Misura dell'assorbimento con retroilluminazione bianca",
+                "mA",
+            ),
+            CurrentRed => (
+                "A0018",
+                "Misura dell'assorbimento con retroilluminazione rossa",
+                "mA",
+            ),
+            CurrentGreen => (
+                "A0019",
+                "Misura dell'assorbimento con retroilluminazione verde",
+                "mA",
+            ),
+            CurrentBlue => (
+                "A0020",
+                "Misura dell'assorbimento con retroilluminazione blu",
+                "mA",
+            ),
+            CurrentOutput => (
+                "A0021",
+                "Misura dell'assorbimento con uscita digitale attiva",
+                "mA",
+            ),
             AnalogShortCircuit => (
                 "A007",
                 "Verifica della rilevazione di un cortocircuito sulla linea analogica",
@@ -113,6 +685,7 @@ impl TestStep {
                 "Lettura di una frequenza e verifica del valore",
                 "Hz",
             ),
+            Pulses => ("A0024", "Conteggio di un treno di impulsi", ""),
             OutputShortCircuit => (
                 "A010",
                 "Verifica della rilevazione di un cortocircuito sulla uscita digitale",
@@ -123,19 +696,60 @@ impl TestStep {
                 "Verifica del funzionamento della uscita digitale",
                 "",
             ),
+            WriteManufacturingData => ("A0015", "Scrittura dei dati di produzione", ""),
             FlashingProduction => ("A0012", "Caricamento del firmware finale", ""),
+            OptionBytes => ("A0014", "Programmazione degli option byte", ""),
+            VerifyProduction => ("A0013", "Verifica dell'avvio del firmware finale", ""),
         }
     }
 
+    /// Backlight colour checked by the step, if it is one of the backlight steps
+    pub fn light(self: &Self) -> Option<RgbLight> {
+        use TestStep::*;
+        match self {
+            UiRgbWhite => Some(RgbLight::White),
+            UiRgbRed => Some(RgbLight::Red),
+            UiRgbGreen => Some(RgbLight::Green),
+            UiRgbBlue => Some(RgbLight::Blue),
+            _ => None,
+        }
+    }
+
+    /// Limits in force: the default ones, unless changed on this fixture
     pub fn limits(self: &Self) -> Option<(f64, f64)> {
+        let overrides = LIMIT_OVERRIDES.lock().unwrap_or_else(|e| e.into_inner());
+        self.default_limits().map(|default| {
+            overrides
+                .get(self.metadata().0)
+                .map(|limits| (limits.minimo, limits.massimo))
+                .unwrap_or(default)
+        })
+    }
+
+    pub fn default_limits(self: &Self) -> Option<(f64, f64)> {
         use TestStep::*;
         match self {
             InvertPower => Some((0.0, 0.0)),
             Frequency => Some((1995.0, 2005.0)),
+            // Every pulse sent must be counted
+            Pulses => Some((1000.0, 1000.0)),
             Analog => Some((9.75, 10.25)),
             Check3v3 => Some((3.25, 3.55)),
             Check5v => Some((4.9, 5.1)),
             Check12v => Some((11.9, 12.8)),
+            // 2.5V reference of the fixture, read against the 3.35V supply of the MCP3208: 2%
+            // covers the 1% resistors setting it and the tolerance of the ADC supply
+            CheckVRef => Some((2.45, 2.55)),
+            // 0.5V expected on the pressure input with the fixture load, 20% only tells a
+            // connected input from an open or shorted one
+            Pressure => Some((0.4, 0.6)),
+            // First estimates rather than figures from a specification of the board: wide
+            // enough for any working board, they catch shorted LEDs and missing loads. To be
+            // narrowed on the readings of known good boards, which every report records
+            CurrentIdle => Some((5.0, 40.0)),
+            CurrentWhite => Some((30.0, 150.0)),
+            CurrentRed | CurrentGreen | CurrentBlue => Some((15.0, 80.0)),
+            CurrentOutput => Some((5.0, 60.0)),
             _ => None,
         }
     }
@@ -147,6 +761,12 @@ impl TestStep {
             true // No limits, always ok
         }
     }
+
+    /// False for the steps every following one depends on
+    pub fn skippable(self: &Self) -> bool {
+        use TestStep::*;
+        !matches!(self, CheckVRef | InvertPower | FlashingTest | Connecting)
+    }
 }
 
 impl Model {
@@ -160,18 +780,74 @@ impl Model {
         logs
     }
 
+    /// Production image selected for the variant currently entered
+    pub fn production_firmware(&self) -> Option<&FirmwareImage> {
+        self.firmware
+            .as_ref()
+            .and_then(|f| f.production_for(self.report.barcode.variante.as_str()))
+    }
+
+    /// Fixture parts that reached their maintenance threshold
+    pub fn maintenance_warnings(&self) -> Vec<String> {
+        let thresholds = &self.config.soglie_manutenzione;
+
+        let mut warnings: Vec<String> = self
+            .maintenance
+            .rele
+            .iter()
+            .filter(|(_, cycles)| **cycles >= thresholds.cicli_rele)
+            .map(|(rele, cycles)| format!("Relè {}: {} cicli, sostituire", rele, cycles))
+            .collect();
+
+        if self.maintenance.inserimenti >= thresholds.inserimenti {
+            warnings.push(format!(
+                "Pogo pin: {} inserimenti, sostituire",
+                self.maintenance.inserimenti
+            ));
+        }
+
+        if self.calibration.is_none() {
+            warnings.push(String::from(
+                "Frequenza non calibrata, calibrare dalla manutenzione",
+            ));
+        }
+
+        warnings
+    }
+
     pub fn digiblock_update(&mut self, state: DigiblockState) {
         self.digiblock_state = state;
     }
 
-    pub fn next_light(&mut self) -> RgbLight {
-        self.light = match self.light {
-            RgbLight::White => RgbLight::Red,
-            RgbLight::Red => RgbLight::Green,
-            RgbLight::Green => RgbLight::Blue,
-            RgbLight::Blue => RgbLight::White,
-        };
-        self.light
+    /// Backlight intensity currently shown to the operator
+    pub fn light_intensity(&self) -> u8 {
+        self.config
+            .livelli_retroilluminazione
+            .get(self.light_level)
+            .copied()
+            .unwrap_or(100)
+    }
+
+    pub fn next_light_level(&mut self) -> u8 {
+        let levels = self.config.livelli_retroilluminazione.len().max(1);
+        self.light_level = (self.light_level + 1) % levels;
+        self.light_intensity()
+    }
+
+    /// True if the operator logged in may perform `permission`
+    pub fn allowed(&self, permission: Permission) -> bool {
+        self.operator
+            .as_ref()
+            .map(|o| self.operators.allows(o.ruolo, permission))
+            .unwrap_or(false)
+    }
+
+    /// LCD pattern currently shown to the operator
+    pub fn lcd_pattern(&self) -> LcdPattern {
+        LcdPattern::sequence()
+            .get(self.lcd_pattern)
+            .copied()
+            .unwrap_or(LcdPattern::Normal)
     }
 
     pub fn add_vbat(&mut self, value: Option<f64>) {
@@ -209,9 +885,14 @@ impl Model {
     }
 
     pub fn log(self: &mut Self, msg: impl Into<String> + std::fmt::Display) {
-        use chrono::prelude::*;
-        let now = Utc::now();
-        let time = now.format("%H:%M:%S:%3f");
-        self.logs.push(format!("[{}]: {}", time, msg));
+        logging::info("collaudo", msg, &[]);
+        self.sync_log();
+    }
+
+    /// Collects the records logged since the last call, from any module
+    pub fn sync_log(self: &mut Self) {
+        let (panel, run) = logging::take();
+        self.logs.extend(panel);
+        self.run_log.extend(run);
     }
 }
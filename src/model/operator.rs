@@ -0,0 +1,97 @@
+use serde::{Deserialize, Serialize};
+
+/// Roles in increasing order of privilege
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    #[default]
+    Operatore,
+    Tecnico,
+    Amministratore,
+}
+
+impl Role {
+    pub fn name(self: &Self) -> &'static str {
+        match self {
+            Role::Operatore => "operatore",
+            Role::Tecnico => "tecnico",
+            Role::Amministratore => "amministratore",
+        }
+    }
+}
+
+/// Actions restricted to some roles
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Permission {
+    Retry,
+    SkipStep,
+    Calibration,
+    Maintenance,
+    Limits,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Operator {
+    /// Reported as `MB_OPxx`
+    pub codice: u8,
+    pub nome: String,
+    #[serde(default)]
+    pub ruolo: Role,
+    /// Code read from the badge by the barcode scanner, a credential on its own
+    #[serde(default)]
+    pub badge: Option<String>,
+    /// Salted hash of the PIN, as printed by `tester_digiblock --hash-pin <PIN>`
+    #[serde(default)]
+    pub pin: Option<String>,
+}
+
+impl Operator {
+    pub fn code(self: &Self) -> String {
+        format!("MB_OP{:02}", self.codice)
+    }
+}
+
+/// Lowest role allowed to perform each action
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Permissions {
+    pub riprova: Role,
+    pub salta: Role,
+    pub calibrazione: Role,
+    pub manutenzione: Role,
+    pub limiti: Role,
+}
+
+impl Default for Permissions {
+    fn default() -> Self {
+        Self {
+            riprova: Role::Operatore,
+            salta: Role::Tecnico,
+            calibrazione: Role::Tecnico,
+            manutenzione: Role::Tecnico,
+            limiti: Role::Amministratore,
+        }
+    }
+}
+
+/// Operators allowed to use the tester, described by `operators.yaml`
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct OperatorRegistry {
+    #[serde(default)]
+    pub permessi: Permissions,
+    #[serde(default)]
+    pub operatori: Vec<Operator>,
+}
+
+impl OperatorRegistry {
+    pub fn allows(self: &Self, role: Role, permission: Permission) -> bool {
+        let required = match permission {
+            Permission::Retry => self.permessi.riprova,
+            Permission::SkipStep => self.permessi.salta,
+            Permission::Calibration => self.permessi.calibrazione,
+            Permission::Maintenance => self.permessi.manutenzione,
+            Permission::Limits => self.permessi.limiti,
+        };
+        role >= required
+    }
+}
@@ -5,7 +5,7 @@ use std::{collections::HashMap, fmt, time::Duration};
 use chrono::{DateTime, Datelike, Local, Timelike};
 use serde::Serialize;
 
-use super::{Configuration, TestStep};
+use super::{DigiblockInfo, FlashingError, Operator, RgbLight, TestStep};
 
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 const NAME: &str = env!("CARGO_PKG_NAME");
@@ -37,9 +37,108 @@ pub struct Report {
     pub start: DateTime<Local>,
     pub tests: HashMap<TestStep, TestStepResult>,
     pub barcode: Barcode,
+    pub device: Option<DigiblockInfo>,
+    pub flashing_errors: HashMap<TestStep, FlashingError>,
+    pub attachments: Vec<Attachment>,
+    pub lcd_segments: Vec<LcdSegment>,
+    pub color_readings: Vec<ColorReading>,
+    /// Operator actions during this run
+    pub audit: Vec<AuditEntry>,
 }
 
-#[derive(Clone, Serialize, Default)]
+/// Additional file saved next to the report
+#[derive(Clone, Debug)]
+pub struct Attachment {
+    /// File name, including the extension, appended to the report name
+    pub name: String,
+    pub content: Vec<u8>,
+}
+
+/// Backlight colour measured by the colour sensor
+#[derive(Clone, Debug)]
+pub struct ColorReading {
+    pub light: RgbLight,
+    /// Commanded intensity in percent
+    pub intensity: u8,
+    pub red: u16,
+    pub green: u16,
+    pub blue: u16,
+    pub clear: u16,
+    pub lux: f64,
+    pub passed: bool,
+}
+
+impl ColorReading {
+    /// Share of red, green and blue in the measured light
+    pub fn chromaticity(&self) -> [f64; 3] {
+        let total = self.red as f64 + self.green as f64 + self.blue as f64;
+        if total == 0.0 {
+            return [0.0; 3];
+        }
+        [
+            self.red as f64 / total,
+            self.green as f64 / total,
+            self.blue as f64 / total,
+        ]
+    }
+}
+
+/// Operator actions recorded in the audit log
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AuditAction {
+    Login,
+    Logout,
+    Start,
+    Retry,
+    Skip,
+    /// The operator confirmed a manual check
+    Confirm,
+    /// The operator rejected a manual check
+    Reject,
+    /// The run was closed before reaching the end
+    Abort,
+    Done,
+    ConfigChange,
+    FirmwareUpdate,
+}
+
+impl AuditAction {
+    pub fn name(self: &Self) -> &'static str {
+        match self {
+            AuditAction::Login => "accesso",
+            AuditAction::Logout => "uscita",
+            AuditAction::Start => "inizio",
+            AuditAction::Retry => "riprova",
+            AuditAction::Skip => "salta",
+            AuditAction::Confirm => "conferma",
+            AuditAction::Reject => "rifiuto",
+            AuditAction::Abort => "interruzione",
+            AuditAction::Done => "conclusione",
+            AuditAction::ConfigChange => "modifica configurazione",
+            AuditAction::FirmwareUpdate => "aggiornamento firmware",
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct AuditEntry {
+    pub time: DateTime<Local>,
+    /// Operator code, empty if nobody is logged in
+    pub operator: String,
+    /// Serial number of the board under test, if any
+    pub serial: String,
+    pub action: AuditAction,
+    pub detail: String,
+}
+
+/// Outcome of the camera check of a single LCD segment
+#[derive(Clone, Debug)]
+pub struct LcdSegment {
+    pub name: String,
+    pub passed: bool,
+}
+
+#[derive(Clone, Debug, Serialize, Default)]
 pub struct Barcode {
     pub rif_ordine: String,
     pub rif_fornitore: String,
@@ -96,6 +195,9 @@ pub struct TestStation {
     pub versione: String,
     pub codice_dut: String,
     pub firmware: String,
+    pub firmware_collaudo: String,
+    pub hardware: String,
+    pub uid_mcu: String,
     pub pmont: String,
     pub ordine_forn: u64,
     pub fornitore: u64,
@@ -173,6 +275,12 @@ impl Default for Report {
             start: chrono::offset::Local::now(),
             tests: HashMap::new(),
             barcode: Barcode::default(),
+            device: None,
+            flashing_errors: HashMap::new(),
+            attachments: Vec::new(),
+            lcd_segments: Vec::new(),
+            color_readings: Vec::new(),
+            audit: Vec::new(),
         }
     }
 }
@@ -191,32 +299,25 @@ impl Report {
 
     pub fn serializable(
         self: &Self,
-        config: &Configuration,
+        operator: Option<&Operator>,
         version: String,
     ) -> SerializableReport {
         let mut prove: Vec<SerializableTestStepResult> = Vec::new();
 
-        const TESTS: [TestStep; 16] = [
-            TestStep::InvertPower,
-            TestStep::FlashingTest,
-            TestStep::Connecting,
-            TestStep::UiLeftButton,
-            TestStep::UiRightButton,
-            TestStep::UiLCD,
-            TestStep::UiRgb,
-            TestStep::Check3v3,
-            TestStep::Check5v,
-            TestStep::Check12v,
-            TestStep::AnalogShortCircuit,
-            TestStep::Analog,
-            TestStep::Frequency,
-            TestStep::OutputShortCircuit,
-            TestStep::Output,
-            TestStep::FlashingProduction,
-        ];
-
-        for step in TESTS {
+        let mut codice_di_errore = String::new();
+        let mut note = String::new();
+
+        for step in TestStep::ALL {
             if let Some(result) = self.tests.get(&step) {
+                if !result.success && codice_di_errore.is_empty() {
+                    codice_di_errore = step.metadata().0.into();
+
+                    if let Some(error) = self.flashing_errors.get(&step) {
+                        let (code, description) = error.metadata();
+                        codice_di_errore = format!("{}-{}", codice_di_errore, code);
+                        note = description.into();
+                    }
+                }
                 prove.push(result.clone().into());
             } else {
                 prove.push(SerializableTestStepResult::unexecuted(step));
@@ -225,14 +326,6 @@ impl Report {
 
         let end = chrono::offset::Local::now();
 
-        let mut codice_di_errore = String::new();
-        for p in &prove {
-            if p.esito == "Fail" {
-                codice_di_errore = p.prova.clone();
-                break;
-            }
-        }
-
         let attrezzatura = "BC033".into();
         let istanza = fs::read_to_string("~/istanza_attrezzatura.txt")
             .unwrap_or("".into())
@@ -240,7 +333,7 @@ impl Report {
             .unwrap_or(1);
 
         SerializableReport {
-            formato: 2,
+            formato: 3,
             collaudo: TestStation {
                 attrezzatura,
                 istanza,
@@ -249,6 +342,21 @@ impl Report {
                 versione: VERSION.into(),
                 codice_dut: "SP0866-001".into(),
                 firmware: version,
+                firmware_collaudo: self
+                    .device
+                    .as_ref()
+                    .map(|d| d.firmware_version.clone())
+                    .unwrap_or_default(),
+                hardware: self
+                    .device
+                    .as_ref()
+                    .map(|d| d.hardware_revision.to_string())
+                    .unwrap_or_default(),
+                uid_mcu: self
+                    .device
+                    .as_ref()
+                    .map(|d| d.uid.clone())
+                    .unwrap_or_default(),
                 pmont: self.barcode.rev_hw.clone(),
                 ordine_forn: self.barcode.rif_ordine.parse().unwrap_or(0),
                 fornitore: self.barcode.rif_fornitore.parse().unwrap_or(0),
@@ -268,10 +376,10 @@ impl Report {
                     self.start.second()
                 ),
                 durata: end.sub(self.start).num_seconds() as f64,
-                operatore: format!("MB_OP{:02}", config.operatore),
+                operatore: operator.map(|o| o.code()).unwrap_or_default(),
                 esito: (if self.successful() { "Pass" } else { "Fail" }).into(),
                 codice_di_errore,
-                note: "".into(),
+                note,
             },
             prove,
         }
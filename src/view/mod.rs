@@ -6,7 +6,10 @@ use iced_native::{Alignment, Color};
 
 pub mod style;
 
-use crate::model::{Model, RgbLight, StepState, TestState, TestStep};
+use crate::model::{LcdPattern, Model, Permission, RgbLight, StepState, TestState, TestStep};
+
+/// Id of the badge and PIN input of the login screen
+pub const LOGIN_INPUT: &str = "login";
 
 //TODO: move away
 
@@ -17,10 +20,24 @@ pub enum Event {
     UiOk,
     UiFail,
     Done,
-    UpdateOperator(u8),
+    BadgeRead(String),
+    OperatorCodeRead(String),
+    PinRead(String),
+    Login,
+    Logout,
+    Skip,
     BarcodeRead(usize,String),
     BarcodeSubmit(usize),
     BarcodeReset,
+    SelfTest,
+    Maintenance,
+    /// Zeroes the count of a relay, or of the pogo pin insertions if `None`
+    MaintenanceReset(Option<String>),
+    CalibrateFrequency,
+    /// New minimum, or maximum if true, typed for the step at the index of `limit_inputs`
+    LimitEdited(usize, bool, String),
+    SaveLimits,
+    MaintenanceBack,
 }
 
 pub fn view<'a>(model: &'a Model) -> Element<'a, Event> {
@@ -30,7 +47,11 @@ pub fn view<'a>(model: &'a Model) -> Element<'a, Event> {
     .horizontal_alignment(alignment::Horizontal::Center);*/
 
     let done_button = button("Concludi").on_press(Event::Done);
-    let retry_button = button("Riprova").on_press(Event::Retry);
+    let retry_button = if model.allowed(Permission::Retry) {
+        button("Riprova").on_press(Event::Retry)
+    } else {
+        button("Riprova")
+    };
 
     let barcode = &model.report.barcode;
 
@@ -64,9 +85,22 @@ pub fn view<'a>(model: &'a Model) -> Element<'a, Event> {
                     .on_submit(Event::BarcodeSubmit(5)),
             ]
             .align_items(Alignment::Center),
+            firmware_description(model),
+            vref_description(model),
+            maintenance_warnings(model),
             row![
                 button("Azzera").on_press(Event::BarcodeReset),
-                if model.report.barcode.valid() {
+                button("Autotest").on_press(Event::SelfTest),
+                if model.allowed(Permission::Maintenance) {
+                    button("Manutenzione").on_press(Event::Maintenance)
+                } else {
+                    button("Manutenzione")
+                },
+                if model.report.barcode.valid()
+                    && model.production_firmware().is_some()
+                    && vref_valid(model)
+                    && model.calibration.is_some()
+                {
                     button("Inizia").on_press(Event::Start)
                 } else {
                     button("Inizia")
@@ -77,17 +111,37 @@ pub fn view<'a>(model: &'a Model) -> Element<'a, Event> {
         .align_items(Alignment::Center)
         .spacing(32)
         .into(),
+        TestState::SelfTest => column![text("Autotest attrezzatura in corso...")]
+            .align_items(Alignment::Center)
+            .into(),
+        TestState::FixtureFault => {
+            let mut checks = column![text("Guasto dell'attrezzatura, collaudo bloccato")
+                .style(Color::from([0.8, 0.0, 0.0]))]
+            .align_items(Alignment::Center)
+            .spacing(16);
+
+            for check in model.fixture_checks.iter().filter(|c| !c.passed) {
+                checks = checks.push(text(format!("{}: {}", check.name, check.detail)));
+            }
+
+            checks
+                .push(button("Ripeti autotest").on_press(Event::SelfTest))
+                .into()
+        }
+        TestState::Maintenance => maintenance(model),
+        TestState::Login => login(model),
         TestState::Testing(step, state @ StepState::Waiting) => {
             test_step_description(&model, *step, *state)
         }
-        TestState::Testing(step, state @ StepState::Failed) => column![
-            test_step_description(&model, *step, *state),
-            retry_button,
-            done_button,
-        ]
-        .align_items(Alignment::Center)
-        .spacing(32)
-        .into(),
+        TestState::Testing(step, state @ StepState::Failed) => {
+            let mut failed = column![test_step_description(&model, *step, *state), retry_button]
+                .align_items(Alignment::Center)
+                .spacing(32);
+            if step.skippable() && model.allowed(Permission::SkipStep) {
+                failed = failed.push(button("Salta").on_press(Event::Skip));
+            }
+            failed.push(done_button).into()
+        }
         TestState::Done => column![
             if model.report.successful() {
                 text("Test concluso").style(Color::from([0.0, 0.8, 0.0]))
@@ -115,18 +169,19 @@ pub fn view<'a>(model: &'a Model) -> Element<'a, Event> {
         text(format!("Errore alimentazione")).style(Color::from([0.8, 0.0, 0.0]))
     };
 
-    let operator_list = row![
-        text("Operatore:"),
-        pick_list(
-            (0..99)
-                .collect::<Vec<u8>>()
-                .iter()
-                .map(|x| x + 1)
-                .collect::<Vec<u8>>(),
-            Some(model.config.operatore),
-            Event::UpdateOperator,
-        )
-    ];
+    let operator_list = match (&model.operator, &model.state) {
+        (Some(operator), TestState::Ready) => row![
+            text(format!("Operatore: {} ({})", operator.nome, operator.ruolo.name())),
+            button("Esci").on_press(Event::Logout),
+        ]
+        .align_items(Alignment::Center),
+        (Some(operator), _) => row![text(format!(
+            "Operatore: {} ({})",
+            operator.nome,
+            operator.ruolo.name()
+        ))],
+        (None, _) => row![text("Nessun operatore")],
+    };
 
     column![
         //title,
@@ -147,38 +202,311 @@ pub fn view<'a>(model: &'a Model) -> Element<'a, Event> {
     .into()
 }
 
+fn login(model: &Model) -> Element<'_, Event> {
+    let mut login = column![
+        text("Passare il badge, oppure digitare il codice operatore e il PIN"),
+        text_input("Badge", model.login_badge.as_str())
+            .id(text_input::Id::new(LOGIN_INPUT))
+            .password()
+            .on_input(Event::BadgeRead)
+            .on_submit(Event::Login)
+            .width(Length::Fixed(400.0)),
+        row![
+            text_input("Codice operatore", model.login_code.as_str())
+                .on_input(Event::OperatorCodeRead)
+                .on_submit(Event::Login)
+                .width(Length::Fixed(192.0)),
+            text_input("PIN", model.login_pin.as_str())
+                .password()
+                .on_input(Event::PinRead)
+                .on_submit(Event::Login)
+                .width(Length::Fixed(192.0)),
+        ]
+        .spacing(16),
+    ]
+    .align_items(Alignment::Center)
+    .spacing(32);
+
+    if let Some(error) = model.operators_error.as_ref().or(model.login_error.as_ref()) {
+        login = login.push(text(error).style(Color::from([0.8, 0.0, 0.0])));
+    }
+
+    login.into()
+}
+
+fn maintenance_warnings(model: &Model) -> Element<'_, Event> {
+    let mut warnings = column![].align_items(Alignment::Center);
+
+    for warning in model.maintenance_warnings() {
+        warnings = warnings.push(text(warning).style(Color::from([0.8, 0.4, 0.0])));
+    }
+
+    warnings.into()
+}
+
+fn maintenance(model: &Model) -> Element<'_, Event> {
+    let thresholds = &model.config.soglie_manutenzione;
+
+    let counter = |name: String, count: u64, threshold: u64, reset: Option<String>| {
+        let label = text(format!("{}: {} / {}", name, count, threshold)).width(Length::Fill);
+        row![
+            if count >= threshold {
+                label.style(Color::from([0.8, 0.0, 0.0]))
+            } else {
+                label
+            },
+            button("Azzera").on_press(Event::MaintenanceReset(reset)),
+        ]
+        .align_items(Alignment::Center)
+        .width(Length::Fixed(800.0))
+    };
+
+    let mut counters = column![text("Manutenzione attrezzatura")]
+        .align_items(Alignment::Center)
+        .spacing(16);
+
+    for (rele, cycles) in &model.maintenance.rele {
+        counters = counters.push(counter(
+            format!("Relè {}", rele),
+            *cycles,
+            thresholds.cicli_rele,
+            Some(rele.clone()),
+        ));
+    }
+
+    let calibration = match &model.calibration_result {
+        Some(Err(e)) => {
+            text(format!("Calibrazione fallita: {}", e)).style(Color::from([0.8, 0.0, 0.0]))
+        }
+        _ => match &model.calibration {
+            Some(calibration) => text(format!(
+                "Correzione frequenza: {:.4}",
+                calibration.correzione_frequenza
+            )),
+            None => text("Frequenza non calibrata").style(Color::from([0.8, 0.4, 0.0])),
+        },
+    };
+
+    let mut maintenance = counters
+        .push(counter(
+            String::from("Inserimenti pogo pin"),
+            model.maintenance.inserimenti,
+            thresholds.inserimenti,
+            None,
+        ))
+        .push(
+            row![
+                calibration.width(Length::Fill),
+                if model.allowed(Permission::Calibration) {
+                    button("Calibra frequenza").on_press(Event::CalibrateFrequency)
+                } else {
+                    button("Calibra frequenza")
+                },
+            ]
+            .align_items(Alignment::Center)
+            .width(Length::Fixed(800.0)),
+        );
+
+    if model.allowed(Permission::Limits) {
+        maintenance = maintenance.push(limits_editor(model));
+    }
+
+    maintenance
+        .push(button("Indietro").on_press(Event::MaintenanceBack))
+        .into()
+}
+
+fn limits_editor(model: &Model) -> Element<'_, Event> {
+    let mut rows = column![].spacing(8);
+    for (index, (step, min, max)) in model.limit_inputs.iter().enumerate() {
+        let (code, description, _) = step.metadata();
+        rows = rows.push(
+            row![
+                text(format!("{} {}", code, description)).width(Length::Fill),
+                text_input("Minimo", min.as_str())
+                    .on_input(move |s| Event::LimitEdited(index, false, s))
+                    .width(Length::Fixed(100.0)),
+                text_input("Massimo", max.as_str())
+                    .on_input(move |s| Event::LimitEdited(index, true, s))
+                    .width(Length::Fixed(100.0)),
+            ]
+            .spacing(8)
+            .align_items(Alignment::Center),
+        );
+    }
+
+    let result = match &model.limits_result {
+        Some(Ok(())) => text("Limiti salvati"),
+        Some(Err(e)) => {
+            text(format!("Limiti non salvati: {}", e)).style(Color::from([0.8, 0.0, 0.0]))
+        }
+        None => text(""),
+    };
+
+    column![
+        text("Limiti delle prove"),
+        scrollable(rows).height(Length::Fixed(300.0)),
+        row![
+            result.width(Length::Fill),
+            button("Salva limiti").on_press(Event::SaveLimits),
+        ]
+        .align_items(Alignment::Center),
+    ]
+    .spacing(8)
+    .width(Length::Fixed(800.0))
+    .into()
+}
+
+fn vref_valid(model: &Model) -> bool {
+    model
+        .vref
+        .map(|v| TestStep::CheckVRef.check_limits(v))
+        .unwrap_or(false)
+}
+
+fn vref_description(model: &Model) -> Element<'_, Event> {
+    match model.vref {
+        Some(vref) if vref_valid(model) => {
+            text(format!("Tensione di riferimento {:.2}V", vref)).into()
+        }
+        Some(vref) => text(format!(
+            "Tensione di riferimento {:.2}V fuori dai limiti, verificare l'attrezzatura",
+            vref
+        ))
+        .style(Color::from([0.8, 0.0, 0.0]))
+        .into(),
+        None => text("Impossibile leggere la tensione di riferimento")
+            .style(Color::from([0.8, 0.0, 0.0]))
+            .into(),
+    }
+}
+
+fn firmware_description(model: &Model) -> Element<'_, Event> {
+    if let Some(error) = &model.firmware_error {
+        text(error).style(Color::from([0.8, 0.0, 0.0])).into()
+    } else if let Some(image) = model.production_firmware() {
+        text(format!("Firmware di produzione: {}", image.versione)).into()
+    } else {
+        text("Nessun firmware di produzione per la variante indicata")
+            .style(Color::from([0.8, 0.0, 0.0]))
+            .into()
+    }
+}
+
+fn flashing_progress(model: &Model, step: TestStep) -> Element<'_, Event> {
+    if let Some(flashing) = &model.flashing {
+        let (phase, _, _) = flashing.phase.metadata();
+        let elapsed = flashing.start.elapsed().as_secs();
+
+        let time = match model.flashing_times.get(step.metadata().0) {
+            Some(expected) => format!("{}s / {:.0}s stimati", elapsed, expected),
+            None => format!("{}s", elapsed),
+        };
+
+        column![
+            progress_bar(0.0..=100.0, flashing.overall() * 100.0).width(Length::Fixed(600.0)),
+            text(format!("{} - {}", phase, time)),
+        ]
+        .align_items(Alignment::Center)
+        .spacing(8)
+        .into()
+    } else {
+        column![].into()
+    }
+}
+
 fn test_step_description(model: &Model, step: TestStep, state: StepState) -> Element<Event> {
     let done_button = button("Non funzionante").on_press(Event::UiFail);
     let ok_button = button("Conferma").on_press(Event::UiOk);
 
     use TestStep::*;
     match (step, state) {
+        (CheckVRef, StepState::Waiting) => column![text("Controllo tensione di riferimento")],
+        (CheckVRef, StepState::Failed) => column![text(
+            "Tensione di riferimento fuori dai limiti, verificare l'attrezzatura"
+        )],
+        (Pressure, StepState::Waiting) => column![text("Controllo ingresso pressione")],
+        (Pressure, StepState::Failed) => column![text("Controllo ingresso pressione fallito")],
         (InvertPower, StepState::Waiting) => {
             column![text("Controllo inversione alimentazione in corso")]
         }
         (InvertPower, StepState::Failed) => {
             column![text("Controllo inversione alimentazione fallito")]
         }
-        (FlashingTest, StepState::Waiting) => column![text("Caricamento firmware collaudo...")],
+        (FlashingTest, StepState::Waiting) => column![
+            text("Caricamento firmware collaudo..."),
+            flashing_progress(model, step)
+        ],
         (FlashingTest, StepState::Failed) => column![text("Caricamento firmware fallito")],
         (Connecting, StepState::Waiting) => column![text("Connessione...")],
         (Connecting, StepState::Failed) => column![text("Connessione fallita")],
-        (UiLeftButton, _) => column![text("Premere il tasto sinistro"), done_button],
-        (UiRightButton, _) => column![text("Premere il tasto destro"), done_button],
-        (UiLCD, _) => column![
-            text("Verificare che lo schermo funzioni"),
-            ok_button,
-            done_button
-        ],
-        (UiRgb, _) => {
+        (UiLeftButton, StepState::Waiting) => {
+            column![text("Premere il tasto sinistro"), done_button]
+        }
+        (UiLeftButton, StepState::Failed) => column![text("Tasto sinistro non rilevato")],
+        (UiRightButton, StepState::Waiting) => {
+            column![text("Premere il tasto destro"), done_button]
+        }
+        (UiRightButton, StepState::Failed) => column![text("Tasto destro non rilevato")],
+        (UiLCD, StepState::Waiting) if model.config.camera.is_some() => {
+            column![text("Verifica automatica dello schermo in corso...")]
+        }
+        (UiLCD, StepState::Failed) if model.config.camera.is_some() => {
+            let mut failed = column![text("Verifica automatica dello schermo fallita")];
+            for segment in model.report.lcd_segments.iter().filter(|s| !s.passed) {
+                failed = failed.push(text(format!("Segmento {} difettoso", segment.name)));
+            }
+            failed
+        }
+        (UiLCD, _) => {
+            let pattern = model.lcd_pattern();
+            let mut content = column![
+                text(format!(
+                    "Verificare lo schermo ({} di {})",
+                    model.lcd_pattern + 1,
+                    LcdPattern::sequence().len()
+                )),
+                text(pattern.description()).size(32),
+            ];
+            if let Some(path) = pattern.image() {
+                content = content.push(image(path).height(Length::Fixed(240.0)));
+            }
+            content.push(ok_button).push(done_button)
+        }
+        (UiRgbWhite | UiRgbRed | UiRgbGreen | UiRgbBlue, StepState::Waiting)
+            if model.config.sensore_colore.is_some() =>
+        {
+            column![text("Verifica automatica della retroilluminazione in corso...")]
+        }
+        (UiRgbWhite | UiRgbRed | UiRgbGreen | UiRgbBlue, StepState::Failed)
+            if model.config.sensore_colore.is_some() =>
+        {
+            let mut failed = column![text("Verifica retroilluminazione fallita")];
+            let light = step.light().unwrap_or_default();
+            for reading in model
+                .report
+                .color_readings
+                .iter()
+                .filter(|r| r.light == light && !r.passed)
+            {
+                failed = failed.push(text(format!(
+                    "Colore {} fuori dai limiti (intensita' {}%)",
+                    reading.light.name(),
+                    reading.intensity
+                )));
+            }
+            failed
+        }
+        (UiRgbWhite | UiRgbRed | UiRgbGreen | UiRgbBlue, _) => {
             let light_text = text(format!(
-                "Dovrebbe apparire {}",
-                match model.light {
+                "Dovrebbe apparire {} (intensita' {}%)",
+                match step.light().unwrap_or_default() {
                     RgbLight::White => "bianca",
                     RgbLight::Red => "rossa",
                     RgbLight::Green => "verde",
                     RgbLight::Blue => "blu",
-                }
+                },
+                model.light_intensity()
             ));
 
             column![
@@ -194,6 +522,17 @@ fn test_step_description(model: &Model, step: TestStep, state: StepState) -> Ele
         (Check5v, StepState::Failed) => column![text("Controllo alimentazione 5v fallito")],
         (Check12v, StepState::Waiting) => column![text("Controllo alimentazione 12v")],
         (Check12v, StepState::Failed) => column![text("Controllo alimentazione 12v fallito")],
+        (
+            CurrentIdle | CurrentWhite | CurrentRed | CurrentGreen | CurrentBlue | CurrentOutput,
+            StepState::Waiting,
+        ) => column![text("Misura assorbimento in corso")],
+        (
+            CurrentIdle | CurrentWhite | CurrentRed | CurrentGreen | CurrentBlue | CurrentOutput,
+            StepState::Failed,
+        ) => column![text(format!(
+            "Assorbimento fuori dai limiti: {}",
+            step.metadata().1
+        ))],
         (AnalogShortCircuit, StepState::Waiting) => {
             column![text("Test corto circuito analogico in corso")]
         }
@@ -204,6 +543,8 @@ fn test_step_description(model: &Model, step: TestStep, state: StepState) -> Ele
         (Analog, StepState::Failed) => column![text("Test analogico fallito")],
         (Frequency, StepState::Failed) => column![text("Test frequenza fallito")],
         (Frequency, StepState::Waiting) => column![text("Test frequenza in corso")],
+        (Pulses, StepState::Failed) => column![text("Test conteggio impulsi fallito")],
+        (Pulses, StepState::Waiting) => column![text("Test conteggio impulsi in corso")],
         (OutputShortCircuit, StepState::Waiting) => {
             column![text("Test corto circuito uscita in corso")]
         }
@@ -212,10 +553,27 @@ fn test_step_description(model: &Model, step: TestStep, state: StepState) -> Ele
         }
         (Output, StepState::Waiting) => column![text("Test uscita in corso")],
         (Output, StepState::Failed) => column![text("Test uscita fallito")],
-        (FlashingProduction, StepState::Waiting) => {
-            column![text("Caricamento firmware produzione...")]
+        (WriteManufacturingData, StepState::Waiting) => {
+            column![text("Scrittura dati di produzione...")]
+        }
+        (WriteManufacturingData, StepState::Failed) => {
+            column![text("Scrittura dati di produzione fallita")]
         }
+        (FlashingProduction, StepState::Waiting) => column![
+            text("Caricamento firmware produzione..."),
+            flashing_progress(model, step)
+        ],
         (FlashingProduction, StepState::Failed) => column![text("Caricamento firmware fallito")],
+        (OptionBytes, StepState::Waiting) => column![text("Programmazione option byte...")],
+        (OptionBytes, StepState::Failed) => {
+            column![text("Programmazione option byte fallita")]
+        }
+        (VerifyProduction, StepState::Waiting) => {
+            column![text("Verifica avvio firmware produzione...")]
+        }
+        (VerifyProduction, StepState::Failed) => {
+            column![text("Il firmware di produzione non si avvia")]
+        }
     }
     .align_items(Alignment::Center)
     .spacing(32)
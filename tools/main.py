@@ -23,41 +23,74 @@ def processLogs(dataDir):
     return reports
 
 
-def TestData_Validator(testdata):
+# Keys of the "collaudo" section and the top-level keys, as of each data version
+listaCollaudo = {
+    1: ["attrezzatura", "applicazione", "versione", "codice_dut", "istanza", "stazione",
+        "firmware", "matricola", "data", "ora", "durata", "operatore", "esito", "codice_di_errore", "note"],
+}
+listaCollaudo[2] = listaCollaudo[1] + ["hardware", "ordine_forn", "fornitore", "datario", "pmont",
+                                       "identificativo", "variante"]
+listaCollaudo[3] = listaCollaudo[2] + ["firmware_collaudo", "uid_mcu"]
+
+listaGenerica = {
+    1: ["formato", "collaudo", "prove"],
+    2: ["formato", "collaudo", "prove"],
+    3: ["formato", "collaudo", "prove", "registro"],
+}
+
+# Top-level sections written only when they have content, with the keys of their items
+listaSezioni = {
+    1: {},
+    2: {},
+    3: {
+        "segmenti_lcd": ["segmento", "esito"],
+        "retroilluminazione": ["colore", "intensita", "rosso", "verde", "blu", "chiaro", "lux", "esito"],
+        "registro_azioni": ["data", "operatore", "matricola", "azione", "dettaglio"],
+    },
+}
+
+listaProve = ["prova", "descrizione", "esito",
+              "durata", "udm", "valore", "minimo", "massimo"]
+
+
+def Items_Validator(items, chiavi, versione):
+    for l in chiavi:
+        for item in items:
+            if l not in item:
+                raise RuntimeError(l, " missed key")
+
+    for item in items:
+        for chiave in item:
+            if chiave not in chiavi:
+                raise RuntimeError(chiave, " key not in version %d" % versione)
 
-    if testdata["formato"] != 1:
-        raise RuntimeError(testdata["formato"], ": unsupported data version")
 
-    listaCollaudo = ["attrezzatura", "applicazione", "versione", "codice_dut", "istanza", "stazione",
-                     "firmware", "matricola", "data", "ora", "durata", "operatore", "esito", "codice_di_errore", "note"]
-    listaGenerica = ["formato", "collaudo", "prove" ]
-    listaProve = ["prova", "descrizione", "esito",
-                  "durata", "udm", "valore", "minimo", "massimo"]
+def TestData_Validator(testdata):
+
+    versione = testdata["formato"]
+    if versione not in listaGenerica:
+        raise RuntimeError(versione, ": unsupported data version")
 
-    for l in listaGenerica:
+    for l in listaGenerica[versione]:
         if l not in testdata:
             raise RuntimeError(l, " missed key")
-    for l in listaCollaudo:
+    for l in listaCollaudo[versione]:
         if l not in testdata["collaudo"]:
             raise RuntimeError(l, " missed key")
 
     for x in testdata:
-        if x not in listaGenerica:
-            raise RuntimeError(x, " key not in version 1")
+        if x not in listaGenerica[versione] and x not in listaSezioni[versione]:
+            raise RuntimeError(x, " key not in version %d" % versione)
 
     for x in testdata["collaudo"]:
-        if x not in listaCollaudo:
-            raise RuntimeError(x, " key not in version 1")
+        if x not in listaCollaudo[versione]:
+            raise RuntimeError(x, " key not in version %d" % versione)
 
-    for l in listaProve:
-        for prova in testdata["prove"]:
-            if l not in prova:
-                raise RuntimeError(l, " missed key")
+    Items_Validator(testdata["prove"], listaProve, versione)
 
-    for prova in testdata["prove"]:
-        for chiave in prova:
-            if chiave not in listaProve:
-                raise RuntimeError(chiave, " key not in version 1")
+    for sezione, chiavi in listaSezioni[versione].items():
+        if sezione in testdata:
+            Items_Validator(testdata[sezione], chiavi, versione)
 
 
 def main():